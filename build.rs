@@ -1,5 +1,8 @@
-use embed_resource;
-
 fn main() {
     embed_resource::compile("resources/icon.rc", embed_resource::NONE);
+
+    // intel_tex_2 (via image_dds) bundles C++ objects that MSVC links implicitly
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("linux") {
+        println!("cargo:rustc-link-lib=stdc++");
+    }
 }
@@ -0,0 +1,59 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+};
+
+use crate::constants::{Config, ANY_CLIENT, DEFAULT_HOST};
+
+/// Peer addresses permitted to make requests. Loopback clients are always allowed.
+pub struct AllowedClients {
+    any: bool,
+    addresses: HashSet<IpAddr>,
+}
+
+impl AllowedClients {
+    pub fn from_config(config: &Config) -> Self {
+        let mut any = false;
+        let mut addresses = HashSet::new();
+
+        for client in config.allowed_clients.iter().flatten() {
+            if client == ANY_CLIENT {
+                any = true;
+                continue;
+            }
+
+            if let Ok(address) = client.parse::<IpAddr>() {
+                addresses.insert(canonical_ip(address));
+            }
+        }
+
+        AllowedClients { any, addresses }
+    }
+
+    pub fn allows(&self, peer: Option<&SocketAddr>) -> bool {
+        let Some(peer) = peer else {
+            return false;
+        };
+
+        let ip = canonical_ip(peer.ip());
+
+        ip.is_loopback() || self.any || self.addresses.contains(&ip)
+    }
+}
+
+/// Return the configured bind address, falling back to loopback if missing or invalid
+pub fn bind_host(config: &Config) -> IpAddr {
+    config
+        .host
+        .as_deref()
+        .and_then(|host| host.parse().ok())
+        .unwrap_or_else(|| DEFAULT_HOST.parse().unwrap())
+}
+
+/// Treat IPv4-mapped IPv6 peers (seen when bound to "::") as their IPv4 address
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
@@ -0,0 +1,277 @@
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    imageops, AnimationDecoder, Frames, ImageDecoder, ImageFormat, RgbaImage,
+};
+use serde::Serialize;
+use std::{collections::HashMap, io::Cursor};
+
+use crate::atlas::Sprite;
+use crate::constants::{
+    DEFAULT_SPRITE_PADDING, MAX_ANIMATION_FRAMES, MAX_SPRITE_PADDING, MAX_SPRITE_SHEET_SIZE,
+};
+use crate::errors::ServerError;
+use crate::transform::{decode, source_format};
+use crate::utilities::bounded_param;
+
+/// Frames of an animated image, composited to the full canvas
+pub struct Animation {
+    pub frames: Vec<AnimationFrame>,
+}
+
+pub struct AnimationFrame {
+    pub image: RgbaImage,
+    pub delay_ms: u32,
+}
+
+/// Grid of a sprite sheet, read from `columns` and `padding` query parameters
+pub struct SheetRequest {
+    /// Frames per row, or close to square if not given
+    pub columns: Option<u32>,
+    /// Transparent pixels around every frame
+    pub padding: u32,
+}
+
+/// Where every frame is placed in the sprite sheet
+#[derive(Serialize)]
+pub struct SheetLayout {
+    pub width: u32,
+    pub height: u32,
+    pub padding: u32,
+    pub columns: u32,
+    pub rows: u32,
+    pub frame_width: u32,
+    pub frame_height: u32,
+    /// Sum of every frame's delay
+    pub duration_ms: u32,
+    pub frames: Vec<SheetFrame>,
+}
+
+/// How many frames to decode, stopping as soon as the rest are not needed
+enum Wanted<'a> {
+    /// Every frame, as long as the sprite sheet they make stays within its size limit
+    Sheet(&'a SheetRequest),
+    /// The frame at this index and those before it
+    Frame(u32),
+}
+
+#[derive(Serialize)]
+pub struct SheetFrame {
+    #[serde(flatten)]
+    pub sprite: Sprite,
+    /// How long the frame is shown before the next
+    pub delay_ms: u32,
+}
+
+impl SheetRequest {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<SheetRequest, ServerError> {
+        let columns = match params.get("columns") {
+            None => None,
+            Some(_) => Some(bounded_param(
+                params,
+                "columns",
+                1,
+                1,
+                MAX_ANIMATION_FRAMES,
+            )?),
+        };
+
+        Ok(SheetRequest {
+            columns,
+            padding: bounded_param(
+                params,
+                "padding",
+                DEFAULT_SPRITE_PADDING,
+                0,
+                MAX_SPRITE_PADDING,
+            )?,
+        })
+    }
+
+    /// Columns, rows, width and height of a sheet of `count` frames, which must fit within
+    /// `MAX_SPRITE_SHEET_SIZE`
+    ///
+    /// Adding frames never shrinks the sheet, so this also rejects animations part way through.
+    fn grid(&self, count: u32, frame_size: (u32, u32)) -> Result<[u32; 4], ServerError> {
+        let (frame_width, frame_height) = frame_size;
+        let columns = self
+            .columns
+            .unwrap_or_else(|| (count as f64).sqrt().ceil() as u32)
+            .min(count);
+        let rows = (count + columns - 1) / columns;
+
+        let padding = u64::from(self.padding);
+        let width = u64::from(columns) * (u64::from(frame_width) + padding) + padding;
+        let height = u64::from(rows) * (u64::from(frame_height) + padding) + padding;
+
+        if width > u64::from(MAX_SPRITE_SHEET_SIZE) || height > u64::from(MAX_SPRITE_SHEET_SIZE) {
+            return Err(ServerError::unprocessable(format!(
+                "A {}x{} sprite sheet is larger than {}x{}, request single frames instead",
+                width, height, MAX_SPRITE_SHEET_SIZE, MAX_SPRITE_SHEET_SIZE
+            )));
+        }
+
+        Ok([columns, rows, width as u32, height as u32])
+    }
+}
+
+impl Animation {
+    /// Decode every frame of an animated GIF, PNG or WebP, or the single frame of any other image,
+    /// refusing animations too large for `request`'s sprite sheet before decoding all of them
+    pub fn decode_sheet(
+        bytes: &[u8],
+        path: &str,
+        request: &SheetRequest,
+    ) -> Result<Animation, ServerError> {
+        Animation::decode(bytes, path, Wanted::Sheet(request))
+    }
+
+    /// Decode one frame by its index, without decoding the frames after it
+    pub fn decode_frame(bytes: &[u8], path: &str, index: u32) -> Result<RgbaImage, ServerError> {
+        let mut animation = Animation::decode(bytes, path, Wanted::Frame(index))?;
+        let count = animation.frames.len();
+
+        match animation.frames.pop() {
+            Some(frame) if count > index as usize => Ok(frame.image),
+            _ => Err(ServerError::out_of_range(format!(
+                "frame {} is out of range, the image has {} frames",
+                index, count
+            ))),
+        }
+    }
+
+    fn decode(bytes: &[u8], path: &str, wanted: Wanted) -> Result<Animation, ServerError> {
+        let format = source_format(bytes, path);
+        let invalid =
+            |err| ServerError::unprocessable(format!("Invalid image file {}: {}", path, err));
+
+        let frames = match format {
+            Some(ImageFormat::Gif) => {
+                let decoder = GifDecoder::new(Cursor::new(bytes)).map_err(invalid)?;
+                Some((decoder.dimensions(), decoder.into_frames()))
+            }
+            Some(ImageFormat::Png) => {
+                let decoder = PngDecoder::new(Cursor::new(bytes)).map_err(invalid)?;
+                let canvas = decoder.dimensions();
+                decoder
+                    .is_apng()
+                    .then(|| (canvas, decoder.apng().into_frames()))
+            }
+            Some(ImageFormat::WebP) => {
+                let decoder = WebPDecoder::new(Cursor::new(bytes)).map_err(invalid)?;
+                let canvas = decoder.dimensions();
+                decoder
+                    .has_animation()
+                    .then(|| (canvas, decoder.into_frames()))
+            }
+            _ => None,
+        };
+
+        match frames {
+            Some((canvas, frames)) => collect_frames(frames, canvas, &wanted, path),
+            None => Ok(Animation {
+                frames: vec![AnimationFrame {
+                    image: decode(bytes, format, path)?.to_rgba8(),
+                    delay_ms: 0,
+                }],
+            }),
+        }
+    }
+
+    /// Lay the frames out in rows from left to right
+    pub fn layout(&self, request: &SheetRequest) -> Result<SheetLayout, ServerError> {
+        let (frame_width, frame_height) = self.frames[0].image.dimensions();
+        let [columns, rows, width, height] =
+            request.grid(self.frames.len() as u32, (frame_width, frame_height))?;
+
+        let frames = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                let (column, row) = (index as u32 % columns, index as u32 / columns);
+                let position = (
+                    request.padding + column * (frame_width + request.padding),
+                    request.padding + row * (frame_height + request.padding),
+                );
+
+                SheetFrame {
+                    sprite: Sprite::new(position, (frame_width, frame_height), (width, height)),
+                    delay_ms: frame.delay_ms,
+                }
+            })
+            .collect();
+
+        Ok(SheetLayout {
+            width,
+            height,
+            padding: request.padding,
+            columns,
+            rows,
+            frame_width,
+            frame_height,
+            duration_ms: self.frames.iter().map(|frame| frame.delay_ms).sum(),
+            frames,
+        })
+    }
+
+    /// Copy every frame into its place in the sprite sheet
+    pub fn render(&self, layout: &SheetLayout) -> RgbaImage {
+        let mut sheet = RgbaImage::new(layout.width, layout.height);
+
+        for (frame, placed) in self.frames.iter().zip(&layout.frames) {
+            imageops::replace(
+                &mut sheet,
+                &frame.image,
+                i64::from(placed.sprite.x),
+                i64::from(placed.sprite.y),
+            );
+        }
+
+        sheet
+    }
+}
+
+/// Decode frames one at a time, each the size of the `canvas`, stopping once `wanted` has them all
+fn collect_frames(
+    frames: Frames,
+    canvas: (u32, u32),
+    wanted: &Wanted,
+    path: &str,
+) -> Result<Animation, ServerError> {
+    let mut collected = Vec::new();
+
+    for frame in frames {
+        match wanted {
+            Wanted::Frame(index) if collected.len() as u32 > *index => break,
+            Wanted::Sheet(request) => {
+                request.grid(collected.len() as u32 + 1, canvas)?;
+            }
+            _ => {}
+        }
+        if collected.len() as u32 == MAX_ANIMATION_FRAMES {
+            return Err(ServerError::unprocessable(format!(
+                "{} has more than {} frames",
+                path, MAX_ANIMATION_FRAMES
+            )));
+        }
+
+        let frame = frame.map_err(|err| {
+            ServerError::unprocessable(format!("Invalid image file {}: {}", path, err))
+        })?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+
+        collected.push(AnimationFrame {
+            delay_ms: numerator / denominator.max(1),
+            image: frame.into_buffer(),
+        });
+    }
+
+    if collected.is_empty() {
+        return Err(ServerError::unprocessable(format!(
+            "{} has no frames",
+            path
+        )));
+    }
+
+    Ok(Animation { frames: collected })
+}
@@ -0,0 +1,280 @@
+use image::{imageops, io::Reader as ImageReader, RgbaImage};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use crate::constants::{
+    ATLAS_PATH_SEPARATOR, DEFAULT_ATLAS_SIZE, DEFAULT_SPRITE_PADDING, MAX_ATLAS_SPRITES,
+    MAX_SPRITE_PADDING, MAX_SPRITE_SHEET_SIZE,
+};
+use crate::dds::{read_dds, read_dds_header, DdsTexture, Selection};
+use crate::errors::ServerError;
+use crate::transform::{decode, source_format};
+use crate::utilities::{bounded_param, required_param};
+
+/// Sources and limits of an atlas, read from `paths`, `padding` and `max_size` query parameters
+pub struct AtlasRequest {
+    pub paths: Vec<String>,
+    /// Transparent pixels around every sprite
+    pub padding: u32,
+    /// Largest width or height the atlas may have
+    pub max_size: u32,
+}
+
+/// Where every sprite is placed in the atlas
+#[derive(Serialize)]
+pub struct AtlasLayout {
+    pub width: u32,
+    pub height: u32,
+    pub padding: u32,
+    /// Sprites keyed by the path they were requested with
+    pub sprites: BTreeMap<String, Sprite>,
+}
+
+#[derive(Serialize)]
+pub struct Sprite {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Left, top, right and bottom edges as fractions of the atlas size
+    pub uv: [f32; 4],
+}
+
+impl Sprite {
+    /// Place a sprite of `size` at `position` in an image of `image_size`
+    pub fn new(position: (u32, u32), size: (u32, u32), image_size: (u32, u32)) -> Sprite {
+        let ((x, y), (width, height)) = (position, size);
+        let (image_width, image_height) = (image_size.0 as f32, image_size.1 as f32);
+
+        Sprite {
+            x,
+            y,
+            width,
+            height,
+            uv: [
+                x as f32 / image_width,
+                y as f32 / image_height,
+                (x + width) as f32 / image_width,
+                (y + height) as f32 / image_height,
+            ],
+        }
+    }
+}
+
+impl AtlasRequest {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<AtlasRequest, ServerError> {
+        let mut paths: Vec<String> = Vec::new();
+        for path in required_param(params, "paths")?.split(ATLAS_PATH_SEPARATOR) {
+            let path = path.trim();
+            if !path.is_empty() && !paths.iter().any(|existing| existing == path) {
+                paths.push(path.to_string());
+            }
+        }
+
+        if paths.is_empty() {
+            return Err(ServerError::missing_parameter("paths"));
+        }
+        if paths.len() > MAX_ATLAS_SPRITES {
+            return Err(ServerError::bad_request(format!(
+                "An atlas can hold at most {} sprites",
+                MAX_ATLAS_SPRITES
+            )));
+        }
+
+        Ok(AtlasRequest {
+            paths,
+            padding: bounded_param(
+                params,
+                "padding",
+                DEFAULT_SPRITE_PADDING,
+                0,
+                MAX_SPRITE_PADDING,
+            )?,
+            max_size: bounded_param(
+                params,
+                "max_size",
+                DEFAULT_ATLAS_SIZE,
+                1,
+                MAX_SPRITE_SHEET_SIZE,
+            )?,
+        })
+    }
+
+    /// Place the sprites using only their dimensions, without decoding them
+    pub fn layout(&self) -> Result<AtlasLayout, ServerError> {
+        let sizes = self
+            .paths
+            .iter()
+            .map(|path| dimensions(path))
+            .collect::<Result<Vec<(u32, u32)>, ServerError>>()?;
+
+        pack(&self.paths, &sizes, self.padding, self.max_size)
+    }
+
+    /// Decode every sprite and copy it into its place in the atlas
+    pub fn render(&self, layout: &AtlasLayout) -> Result<RgbaImage, ServerError> {
+        let mut atlas = RgbaImage::new(layout.width, layout.height);
+
+        for path in &self.paths {
+            let sprite = &layout.sprites[path];
+            let image = load(path)?;
+
+            // The file may have been replaced since it was measured
+            if image.dimensions() != (sprite.width, sprite.height) {
+                return Err(ServerError::unprocessable(format!(
+                    "{} changed while building the atlas",
+                    path
+                )));
+            }
+
+            imageops::replace(&mut atlas, &image, i64::from(sprite.x), i64::from(sprite.y));
+        }
+
+        Ok(atlas)
+    }
+}
+
+/// Shelf packing: the tallest sprites first, in rows no wider than the narrowest atlas that fits
+fn pack(
+    paths: &[String],
+    sizes: &[(u32, u32)],
+    padding: u32,
+    max_size: u32,
+) -> Result<AtlasLayout, ServerError> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(sizes[index].1));
+
+    let padded = |size: u32| u64::from(size) + u64::from(padding);
+    let area: u64 = sizes
+        .iter()
+        .map(|&(width, height)| padded(width) * padded(height))
+        .sum();
+    let widest = sizes
+        .iter()
+        .map(|&(width, _)| padded(width))
+        .max()
+        .unwrap_or(0);
+
+    // Start near a square and widen until the rows fit under the height limit
+    let mut row_width = ((area as f64).sqrt().ceil() as u64)
+        .max(widest + u64::from(padding))
+        .next_power_of_two();
+
+    loop {
+        let limit = row_width.min(u64::from(max_size));
+        if widest + u64::from(padding) > limit {
+            break;
+        }
+
+        let positions = shelves(&order, sizes, padding, limit);
+        let width = positions
+            .iter()
+            .map(|&(index, x, _)| padded(sizes[index].0) + x)
+            .max()
+            .unwrap_or(0);
+        let height = positions
+            .iter()
+            .map(|&(index, _, y)| padded(sizes[index].1) + y)
+            .max()
+            .unwrap_or(0);
+
+        if height <= u64::from(max_size) {
+            let (width, height) = (width.max(1) as u32, height.max(1) as u32);
+            let sprites = positions
+                .into_iter()
+                .map(|(index, x, y)| {
+                    let (sprite_width, sprite_height) = sizes[index];
+                    let sprite = Sprite::new(
+                        (x as u32, y as u32),
+                        (sprite_width, sprite_height),
+                        (width, height),
+                    );
+                    (paths[index].clone(), sprite)
+                })
+                .collect();
+
+            return Ok(AtlasLayout {
+                width,
+                height,
+                padding,
+                sprites,
+            });
+        }
+
+        if limit == u64::from(max_size) {
+            break;
+        }
+        row_width *= 2;
+    }
+
+    Err(ServerError::unprocessable(format!(
+        "The sprites do not fit in a {}x{} atlas",
+        max_size, max_size
+    )))
+}
+
+/// Place sprites left to right in rows of at most `limit` pixels, returning their top left corners
+fn shelves(
+    order: &[usize],
+    sizes: &[(u32, u32)],
+    padding: u32,
+    limit: u64,
+) -> Vec<(usize, u64, u64)> {
+    let padding = u64::from(padding);
+    let mut positions = Vec::with_capacity(order.len());
+    let (mut x, mut y, mut row_height) = (padding, padding, 0);
+
+    for &index in order {
+        let (width, height) = (u64::from(sizes[index].0), u64::from(sizes[index].1));
+
+        if x + width + padding > limit && x > padding {
+            x = padding;
+            y += row_height + padding;
+            row_height = 0;
+        }
+
+        positions.push((index, x, y));
+        x += width + padding;
+        row_height = row_height.max(height);
+    }
+
+    positions
+}
+
+/// Width and height of an image or DDS texture, read from its header
+fn dimensions(path: &str) -> Result<(u32, u32), ServerError> {
+    if is_dds(path) {
+        let dds = read_dds_header(path)?;
+        return Ok((dds.get_width(), dds.get_height()));
+    }
+
+    ImageReader::open(path)
+        .map_err(|err| ServerError::from_io(&err, path))?
+        .with_guessed_format()
+        .map_err(|err| ServerError::from_io(&err, path))?
+        .into_dimensions()
+        .map_err(|err| {
+            ServerError::unsupported_media_type(format!("Could not read size of {}: {}", path, err))
+        })
+}
+
+/// Decode an image, or the full size first image of a DDS texture
+fn load(path: &str) -> Result<RgbaImage, ServerError> {
+    if is_dds(path) {
+        let dds = read_dds(path)?;
+        return DdsTexture::new(&dds)?.image(&Selection::default());
+    }
+
+    let bytes = fs::read(path).map_err(|err| ServerError::from_io(&err, path))?;
+    Ok(decode(&bytes, source_format(&bytes, path), path)?.to_rgba8())
+}
+
+fn is_dds(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("dds"))
+}
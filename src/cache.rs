@@ -0,0 +1,550 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+use url::form_urlencoded;
+
+use crate::constants::{CacheConfig, DEFAULT_CACHE_DIRECTORY, DEFAULT_CACHE_DISK_MB};
+use crate::errors::ServerError;
+use crate::utilities::{configured_directory, temporary_path};
+use crate::CONFIG;
+
+const MEGABYTE: u64 = 1024 * 1024;
+const DISK_EXTENSION: &str = "cache";
+
+/// A conversion of a file as it was when converted, so editing the file makes its key stale
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub struct CacheKey {
+    path: PathBuf,
+    modified: u128,
+    size: u64,
+    /// Kind of conversion and the parameters that affect its output, percent-encoded so that no
+    /// value can pass for another parameter
+    params: String,
+}
+
+/// Converted bytes and their MIME type
+#[derive(Clone)]
+pub struct Cached {
+    pub bytes: Vec<u8>,
+    pub mime_type: String,
+}
+
+struct Entry {
+    cached: Cached,
+    last_used: u64,
+}
+
+/// In-memory LRU of conversion results limited to a number of bytes
+#[derive(Default)]
+struct TransformCache {
+    entries: HashMap<CacheKey, Entry>,
+    /// Keys ordered from least to most recently used
+    recency: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    bytes: u64,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    hits: u64,
+    misses: u64,
+    memory: TierStats,
+    disk: TierStats,
+}
+
+#[derive(Serialize)]
+struct TierStats {
+    enabled: bool,
+    entries: usize,
+    bytes: u64,
+    budget_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    directory: Option<String>,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<TransformCache> = Mutex::new(TransformCache::default());
+}
+
+impl CacheKey {
+    /// Return the key for a conversion of the file at `path`, or `None` if the file can't be read
+    pub fn new(
+        kind: &str,
+        path: &str,
+        params: &HashMap<String, String>,
+        names: &[&str],
+    ) -> Option<CacheKey> {
+        let path = fs::canonicalize(path).ok()?;
+        let metadata = fs::metadata(&path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos();
+
+        // Values keep their case, as free text like `text` renders differently in each
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        for name in names {
+            if let Some(value) = params.get(*name) {
+                query.append_pair(name, value);
+            }
+        }
+        let key_params = format!("{}?{}", kind, query.finish());
+
+        Some(CacheKey {
+            path,
+            modified,
+            size: metadata.len(),
+            params: key_params,
+        })
+    }
+
+    /// Return the key for a conversion combining several files, or `None` if any can't be read
+    ///
+    /// The key is filed under the first file and versioned by the newest, so editing any of them
+    /// makes it stale.
+    pub fn for_files(
+        kind: &str,
+        paths: &[String],
+        params: &HashMap<String, String>,
+        names: &[&str],
+    ) -> Option<CacheKey> {
+        let keys = paths
+            .iter()
+            .map(|path| CacheKey::new(kind, path, params, names))
+            .collect::<Option<Vec<CacheKey>>>()?;
+        let first = keys.first()?;
+
+        let mut query = form_urlencoded::Serializer::new(first.params.clone());
+        for key in &keys {
+            query.append_pair("file", &key.path.to_string_lossy());
+        }
+        let key_params = query.finish();
+
+        Some(CacheKey {
+            path: first.path.clone(),
+            modified: keys.iter().map(|key| key.modified).max()?,
+            size: keys.iter().map(|key| key.size).sum(),
+            params: key_params,
+        })
+    }
+
+    /// Return a strong entity tag for this version of the file and conversion
+    pub fn etag(&self) -> String {
+        let digest = Sha256::digest(format!(
+            "{}|{}|{}|{}",
+            self.path.display(),
+            self.modified,
+            self.size,
+            self.params
+        ));
+        let hex: String = digest[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("\"{}\"", hex)
+    }
+
+    /// Return the file's modification time in whole seconds since the Unix epoch
+    pub fn modified_seconds(&self) -> u64 {
+        (self.modified / 1_000_000_000) as u64
+    }
+
+    /// Whether both keys are for the same conversion of the same file, whatever its version
+    fn same_conversion(&self, other: &CacheKey) -> bool {
+        self.path == other.path && self.params == other.params
+    }
+
+    /// Disk file name, shared by every version of the file so a newer one replaces it
+    fn file_name(&self) -> String {
+        let digest = Sha256::digest(format!("{}|{}", self.path.display(), self.params));
+        let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}.{}", &hex[..32], DISK_EXTENSION)
+    }
+
+    /// First line of a disk file, identifying the version of the source it was converted from
+    fn header(&self, mime_type: &str) -> String {
+        format!(
+            "{}\t{}\t{}\t{}",
+            self.modified,
+            self.size,
+            mime_type,
+            self.path.display()
+        )
+    }
+}
+
+impl TransformCache {
+    fn get(&mut self, key: &CacheKey) -> Option<Cached> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.last_used);
+        self.recency.insert(tick, key.clone());
+        entry.last_used = tick;
+        self.hits += 1;
+
+        Some(entry.cached.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, cached: Cached, budget: u64) {
+        self.remove_where(|existing| existing.same_conversion(&key));
+
+        let size = cached.bytes.len() as u64;
+        if size > budget {
+            return;
+        }
+
+        self.tick += 1;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                cached,
+                last_used: self.tick,
+            },
+        );
+        self.bytes += size;
+
+        self.trim(budget);
+    }
+
+    /// Drop the least recently used entries until the cache fits in `budget` bytes
+    fn trim(&mut self, budget: u64) {
+        while self.bytes > budget {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.cached.bytes.len() as u64;
+            }
+        }
+    }
+
+    fn remove_where(&mut self, predicate: impl Fn(&CacheKey) -> bool) -> usize {
+        let keys: Vec<CacheKey> = self
+            .entries
+            .keys()
+            .filter(|key| predicate(key))
+            .cloned()
+            .collect();
+
+        for key in &keys {
+            if let Some(entry) = self.entries.remove(key) {
+                self.recency.remove(&entry.last_used);
+                self.bytes -= entry.cached.bytes.len() as u64;
+            }
+        }
+
+        keys.len()
+    }
+}
+
+/// Return the cached result of a conversion, or run it and cache the result
+///
+/// Without a key (e.g. the file's metadata could not be read) the conversion always runs.
+pub fn cached_conversion<M: Into<String>>(
+    key: Option<CacheKey>,
+    convert: impl FnOnce() -> Result<(Vec<u8>, M), ServerError>,
+) -> Result<Cached, ServerError> {
+    let Some(key) = key else {
+        let (bytes, mime_type) = convert()?;
+        return Ok(Cached {
+            bytes,
+            mime_type: mime_type.into(),
+        });
+    };
+
+    if let Some(cached) = lookup(&key) {
+        return Ok(cached);
+    }
+
+    let (bytes, mime_type) = convert()?;
+    let cached = Cached {
+        bytes,
+        mime_type: mime_type.into(),
+    };
+    store(key, &cached);
+
+    Ok(cached)
+}
+
+/// Return a cached conversion from memory, or from disk if the disk tier is enabled
+pub fn lookup(key: &CacheKey) -> Option<Cached> {
+    let settings = settings();
+
+    if let Some(cached) = CACHE.lock().unwrap().get(key) {
+        return Some(cached);
+    }
+
+    // Read outside the lock so slow disks don't hold up other lookups
+    let from_disk = disk_directory(&settings).and_then(|directory| read_disk(&directory, key));
+
+    let mut cache = CACHE.lock().unwrap();
+    match from_disk {
+        Some(cached) => {
+            cache.hits += 1;
+            cache.insert(key.clone(), cached.clone(), memory_budget(&settings));
+            Some(cached)
+        }
+        None => {
+            cache.misses += 1;
+            None
+        }
+    }
+}
+
+/// Keep a conversion result in memory and, if enabled, on disk
+pub fn store(key: CacheKey, cached: &Cached) {
+    let settings = settings();
+
+    if let Some(directory) = disk_directory(&settings) {
+        if let Err(err) = write_disk(&directory, &key, cached) {
+            log::warn!(
+                "Could not write to cache directory {}: {}",
+                directory.display(),
+                err
+            );
+        }
+        trim_disk(&directory, disk_budget(&settings));
+    }
+
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(key, cached.clone(), memory_budget(&settings));
+}
+
+/// Remove every cached conversion, or only those of the file at `path`, returning how many
+pub fn purge(path: Option<&Path>) -> usize {
+    let settings = settings();
+    let path = path.map(|path| fs::canonicalize(path).unwrap_or(path.to_path_buf()));
+
+    let mut removed = CACHE
+        .lock()
+        .unwrap()
+        .remove_where(|key| path.as_ref().map_or(true, |path| &key.path == path));
+
+    if let Some(directory) = disk_directory(&settings) {
+        for file in disk_files(&directory) {
+            let matches = match &path {
+                Some(path) => {
+                    read_header(&file.path).is_some_and(|header| Path::new(&header.source) == path)
+                }
+                None => true,
+            };
+
+            if matches && fs::remove_file(&file.path).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+
+    removed
+}
+
+pub fn stats() -> CacheStats {
+    let settings = settings();
+    let directory = disk_directory(&settings);
+    let files = directory.as_deref().map(disk_files).unwrap_or_default();
+    let cache = CACHE.lock().unwrap();
+
+    CacheStats {
+        hits: cache.hits,
+        misses: cache.misses,
+        memory: TierStats {
+            enabled: memory_budget(&settings) > 0,
+            entries: cache.entries.len(),
+            bytes: cache.bytes,
+            budget_bytes: memory_budget(&settings),
+            directory: None,
+        },
+        disk: TierStats {
+            enabled: directory.is_some(),
+            entries: files.len(),
+            bytes: files.iter().map(|file| file.size).sum(),
+            budget_bytes: disk_budget(&settings),
+            directory: directory.map(|directory| directory.to_string_lossy().to_string()),
+        },
+    }
+}
+
+fn settings() -> CacheConfig {
+    CONFIG
+        .read()
+        .unwrap()
+        .config
+        .cache
+        .clone()
+        .unwrap_or_default()
+        .resolved()
+}
+
+fn memory_budget(settings: &CacheConfig) -> u64 {
+    settings.memory_mb.unwrap_or(0) * MEGABYTE
+}
+
+fn disk_budget(settings: &CacheConfig) -> u64 {
+    settings.disk_mb.unwrap_or(DEFAULT_CACHE_DISK_MB) * MEGABYTE
+}
+
+/// Return the disk cache directory if the disk tier is enabled
+fn disk_directory(settings: &CacheConfig) -> Option<PathBuf> {
+    if settings.disk_enabled != Some(true) {
+        return None;
+    }
+
+    configured_directory(settings.disk_directory.as_deref(), DEFAULT_CACHE_DIRECTORY).ok()
+}
+
+struct DiskHeader {
+    modified: u128,
+    size: u64,
+    mime_type: String,
+    source: String,
+}
+
+struct DiskFile {
+    path: PathBuf,
+    size: u64,
+    modified: std::time::SystemTime,
+}
+
+fn parse_header(line: &str) -> Option<DiskHeader> {
+    let mut fields = line.trim_end_matches(['\r', '\n']).splitn(4, '\t');
+
+    Some(DiskHeader {
+        modified: fields.next()?.parse().ok()?,
+        size: fields.next()?.parse().ok()?,
+        mime_type: fields.next()?.to_string(),
+        source: fields.next()?.to_string(),
+    })
+}
+
+fn read_header(path: &Path) -> Option<DiskHeader> {
+    let mut line = String::new();
+    BufReader::new(File::open(path).ok()?)
+        .read_line(&mut line)
+        .ok()?;
+    parse_header(&line)
+}
+
+/// Read a conversion from disk, deleting it if it was made from an older version of the file
+fn read_disk(directory: &Path, key: &CacheKey) -> Option<Cached> {
+    let path = directory.join(key.file_name());
+    let mut reader = BufReader::new(File::open(&path).ok()?);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let header = parse_header(&line)?;
+
+    if header.modified != key.modified || header.size != key.size {
+        let _ = fs::remove_file(&path);
+        return None;
+    }
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).ok()?;
+
+    Some(Cached {
+        bytes,
+        mime_type: header.mime_type,
+    })
+}
+
+/// Write through a temporary file so a concurrent reader never sees a partial entry
+fn write_disk(directory: &Path, key: &CacheKey, cached: &Cached) -> std::io::Result<()> {
+    fs::create_dir_all(directory)?;
+
+    let path = directory.join(key.file_name());
+    let temporary = temporary_path(&path);
+
+    let mut file = File::create(&temporary)?;
+    writeln!(file, "{}", key.header(&cached.mime_type))?;
+    file.write_all(&cached.bytes)?;
+    drop(file);
+
+    fs::rename(&temporary, &path)
+}
+
+fn disk_files(directory: &Path) -> Vec<DiskFile> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == DISK_EXTENSION)
+        })
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some(DiskFile {
+                path: entry.path(),
+                size: metadata.len(),
+                modified: metadata.modified().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Remove the oldest files until the disk cache fits in `budget` bytes
+fn trim_disk(directory: &Path, budget: u64) {
+    let mut files = disk_files(directory);
+    let mut total: u64 = files.iter().map(|file| file.size).sum();
+
+    files.sort_by_key(|file| file.modified);
+
+    for file in files {
+        if total <= budget {
+            break;
+        }
+        if fs::remove_file(&file.path).is_ok() {
+            total -= file.size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(query: &[(&str, &str)]) -> CacheKey {
+        let params = query
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        CacheKey::new("text", "Cargo.toml", &params, &["text", "size"]).unwrap()
+    }
+
+    #[test]
+    fn values_keep_their_case() {
+        assert!(key(&[("text", "Hello")]) != key(&[("text", "hello")]));
+    }
+
+    #[test]
+    fn values_cannot_pass_for_other_parameters() {
+        let injected = key(&[("text", "a;size=40")]);
+        let separate = key(&[("text", "a"), ("size", "40")]);
+        assert!(injected != separate);
+        assert_ne!(injected.etag(), separate.etag());
+
+        let injected = key(&[("text", "a&size=40")]);
+        assert!(injected != separate);
+    }
+}
@@ -0,0 +1,209 @@
+use std::io::Read;
+use tiny_http::{Header, Request, Response, ResponseBox, StatusCode};
+
+use crate::cache::CacheKey;
+use crate::constants::CACHE_CONTROL;
+use crate::utilities::{civil_from_days, days_from_civil, empty_response_with_status};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Whether the request's `If-None-Match` or `If-Modified-Since` shows the client has this version
+///
+/// As in RFC 9110, `If-Modified-Since` is ignored when `If-None-Match` is present.
+pub fn is_not_modified(request: &Request, key: &CacheKey) -> bool {
+    if let Some(tags) = header_value(request, "If-None-Match") {
+        let etag = key.etag();
+        return tags
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+
+    header_value(request, "If-Modified-Since")
+        .and_then(parse_http_date)
+        .is_some_and(|since| key.modified_seconds() <= since)
+}
+
+/// Whether a `Range` should be honoured, i.e. any `If-Range` names the version being sent
+pub fn range_allowed(request: &Request, key: &Option<CacheKey>) -> bool {
+    match header_value(request, "If-Range") {
+        None => true,
+        Some(value) => key.as_ref().is_some_and(|key| {
+            value.trim() == key.etag() || parse_http_date(value) == Some(key.modified_seconds())
+        }),
+    }
+}
+
+/// Add `ETag`, `Last-Modified` and `Cache-Control` headers describing the version being sent
+pub fn with_validators<R: Read>(mut response: Response<R>, key: &CacheKey) -> Response<R> {
+    for header in validator_headers(key) {
+        response.add_header(header);
+    }
+    response
+}
+
+/// Add validators when the file's version is known, i.e. its metadata could be read
+pub fn with_key_validators<R: Read>(response: Response<R>, key: &Option<CacheKey>) -> Response<R> {
+    match key {
+        Some(key) => with_validators(response, key),
+        None => response,
+    }
+}
+
+/// Return a `304 Not Modified` response for the version the client already has
+pub fn not_modified_response(key: &CacheKey) -> ResponseBox {
+    with_validators(empty_response_with_status(StatusCode(304)), key)
+}
+
+fn validator_headers(key: &CacheKey) -> Vec<Header> {
+    vec![
+        Header::from_bytes(&b"ETag"[..], key.etag().as_bytes()).unwrap(),
+        Header::from_bytes(
+            &b"Last-Modified"[..],
+            http_date(key.modified_seconds()).as_bytes(),
+        )
+        .unwrap(),
+        Header::from_bytes(&b"Cache-Control"[..], CACHE_CONTROL.as_bytes()).unwrap(),
+    ]
+}
+
+/// Return the value of a request header, matching its name case-insensitively
+pub fn header_value<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Format seconds since the Unix epoch as an IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+fn http_date(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64;
+    let seconds_of_day = seconds % 86_400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds_of_day / 3_600,
+        seconds_of_day % 3_600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Parse an IMF-fixdate into seconds since the Unix epoch, ignoring the obsolete formats
+fn parse_http_date(value: &str) -> Option<u64> {
+    let (_weekday, rest) = value.trim().split_once(", ")?;
+    let mut parts = rest.split(' ');
+
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS.iter().position(|month| *month == month_name)? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+
+    let mut time = parts.next()?.split(':');
+    let hours: i64 = time.next()?.parse().ok()?;
+    let minutes: i64 = time.next()?.parse().ok()?;
+    let seconds: i64 = time.next()?.parse().ok()?;
+
+    if parts.next()? != "GMT" {
+        return None;
+    }
+
+    let total = days_from_civil(year, month, day) * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+    u64::try_from(total).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use tiny_http::TestRequest;
+
+    use super::*;
+
+    fn key() -> CacheKey {
+        CacheKey::new("image", "Cargo.toml", &HashMap::new(), &[]).unwrap()
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        headers
+            .iter()
+            .fold(
+                TestRequest::new().with_path("/image"),
+                |request, (name, value)| {
+                    request
+                        .with_header(Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap())
+                },
+            )
+            .into()
+    }
+
+    #[test]
+    fn http_dates_round_trip() {
+        assert_eq!(http_date(784_111_777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784_111_777)
+        );
+
+        for seconds in [0, 951_782_400, 1_700_000_000, 4_102_444_799] {
+            assert_eq!(parse_http_date(&http_date(seconds)), Some(seconds));
+        }
+    }
+
+    #[test]
+    fn ignores_obsolete_date_formats() {
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let key = key();
+        let future = http_date(key.modified_seconds() + 60);
+        let past = http_date(key.modified_seconds() - 60);
+
+        let stale_tag = request(&[
+            ("If-None-Match", "\"0000000000000000\""),
+            ("If-Modified-Since", &future),
+        ]);
+        assert!(!is_not_modified(&stale_tag, &key));
+
+        let current_tag = request(&[("If-None-Match", &key.etag()), ("If-Modified-Since", &past)]);
+        assert!(is_not_modified(&current_tag, &key));
+
+        assert!(is_not_modified(
+            &request(&[("If-Modified-Since", &future)]),
+            &key
+        ));
+        assert!(!is_not_modified(
+            &request(&[("If-Modified-Since", &past)]),
+            &key
+        ));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly_and_if_range_strongly() {
+        let key = key();
+        let weak = format!("W/{}", key.etag());
+
+        let listed = format!("\"0000000000000000\", {}", weak);
+        assert!(is_not_modified(
+            &request(&[("If-None-Match", &listed)]),
+            &key
+        ));
+        assert!(is_not_modified(&request(&[("If-None-Match", "*")]), &key));
+
+        let key = Some(key);
+        assert!(!range_allowed(&request(&[("If-Range", &weak)]), &key));
+        let strong = key.as_ref().unwrap().etag();
+        assert!(range_allowed(&request(&[("If-Range", &strong)]), &key));
+    }
+}
@@ -0,0 +1,503 @@
+use log::LevelFilter;
+use serde::Serialize;
+use std::{
+    fs,
+    io::{ErrorKind, Result as IoResult},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::access::AllowedClients;
+use crate::constants::{
+    Config, ANY_CLIENT, CACHE_KEYS, CONFIG_KEYS, CONFIG_NAME, CONFIG_POLL_INTERVAL,
+    MAX_CACHE_DISK_MB, MAX_CACHE_MEMORY_MB, MAX_QUEUE_DEPTH, MAX_SHUTDOWN_TIMEOUT_SECONDS,
+    MAX_TEXTURE_SIZE, MAX_WATCHDOG_INTERVAL_MS, MAX_WORKERS, MIN_TEXTURE_SIZE,
+    MIN_WATCHDOG_INTERVAL_MS, WATCHDOG_KEYS,
+};
+use crate::logging;
+use crate::utilities::executable_dir;
+use crate::whitelist::is_sha256_hex;
+use crate::CONFIG;
+
+/// A problem found while reading `config.json`, located by line and column where possible
+#[derive(Clone, Serialize)]
+pub struct ConfigError {
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+/// The settings currently in effect along with how they were arrived at
+pub struct ConfigState {
+    pub path: PathBuf,
+    /// Config with invalid values removed, so unset fields fall back to defaults
+    pub config: Config,
+    pub errors: Vec<ConfigError>,
+    pub allowed_clients: AllowedClients,
+    /// Resolved settings the server was started with, for those that only apply on startup
+    pub startup: Option<Config>,
+}
+
+impl Default for ConfigState {
+    fn default() -> Self {
+        let config = Config::default();
+
+        ConfigState {
+            path: PathBuf::from(CONFIG_NAME),
+            allowed_clients: AllowedClients::from_config(&config),
+            config,
+            errors: Vec::new(),
+            startup: None,
+        }
+    }
+}
+
+impl ConfigState {
+    pub fn load(path: &Path) -> Self {
+        let (config, errors) = read_config(path);
+
+        ConfigState {
+            path: path.to_path_buf(),
+            allowed_clients: AllowedClients::from_config(&config),
+            config,
+            errors,
+            startup: None,
+        }
+    }
+
+    /// Return the settings in effect, which for those only applied on startup are the values the
+    /// server was started with rather than the file's
+    pub fn effective(&self) -> Config {
+        let mut effective = self.config.resolved();
+
+        if let Some(startup) = &self.startup {
+            effective.port = startup.port;
+            effective.host = startup.host.clone();
+            effective.workers = startup.workers;
+            effective.slow_workers = startup.slow_workers;
+            effective.queue_depth = startup.queue_depth;
+        }
+
+        effective
+    }
+
+    /// Whether the file changes a setting that only applies on startup
+    pub fn restart_required(&self) -> bool {
+        match &self.startup {
+            Some(startup) => {
+                let resolved = self.config.resolved();
+                resolved.port != startup.port
+                    || resolved.host != startup.host
+                    || resolved.workers != startup.workers
+                    || resolved.slow_workers != startup.slow_workers
+                    || resolved.queue_depth != startup.queue_depth
+            }
+            None => false,
+        }
+    }
+}
+
+/// Return the path of `config.json` next to the executable
+pub fn config_path() -> IoResult<PathBuf> {
+    Ok(executable_dir()?.join(CONFIG_NAME))
+}
+
+/// Poll `config.json` for changes and re-apply the settings that can change at runtime
+pub fn watch_config(path: PathBuf) {
+    thread::spawn(move || {
+        let mut last_seen = file_signature(&path);
+
+        loop {
+            thread::sleep(Duration::from_secs(CONFIG_POLL_INTERVAL));
+
+            let signature = file_signature(&path);
+            if signature == last_seen {
+                continue;
+            }
+            last_seen = signature;
+
+            let mut state = ConfigState::load(&path);
+            report_errors(&state.errors);
+            logging::set_level(state.config.log_level());
+
+            let mut current = CONFIG.write().unwrap();
+            state.startup = current.startup.take();
+            *current = state;
+
+            if current.restart_required() {
+                log::info!(
+                    "Reloaded {}: some changes apply after a restart",
+                    CONFIG_NAME
+                );
+            } else {
+                log::info!("Reloaded {}", CONFIG_NAME);
+            }
+        }
+    });
+}
+
+pub fn report_errors(errors: &[ConfigError]) {
+    for error in errors {
+        match (error.line, error.column) {
+            (Some(line), Some(column)) => {
+                log::warn!("{} ({}:{}): {}", CONFIG_NAME, line, column, error.message)
+            }
+            _ => log::warn!("{}: {}", CONFIG_NAME, error.message),
+        }
+    }
+}
+
+fn file_signature(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn read_config(path: &Path) -> (Config, Vec<ConfigError>) {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) if err.kind() == ErrorKind::NotFound => return (Config::default(), Vec::new()),
+        Err(err) => {
+            let error = ConfigError {
+                message: format!("Could not read file: {}", err),
+                line: None,
+                column: None,
+            };
+            return (Config::default(), vec![error]);
+        }
+    };
+
+    let mut errors = Vec::new();
+
+    let mut config: Config = match serde_json::from_str(&source) {
+        Ok(config) => config,
+        Err(err) => {
+            errors.push(ConfigError {
+                message: format!("Invalid JSON, using defaults: {}", strip_location(&err)),
+                line: Some(err.line()),
+                column: Some(err.column()),
+            });
+            return (Config::default(), errors);
+        }
+    };
+
+    check_unknown_keys(&source, &mut errors);
+    validate(&mut config, &source, &mut errors);
+
+    (config, errors)
+}
+
+/// Unknown keys are most likely typos of known ones, which would otherwise be silently ignored
+fn check_unknown_keys(source: &str, errors: &mut Vec<ConfigError>) {
+    let Ok(serde_json::Value::Object(map)) = serde_json::from_str(source) else {
+        return;
+    };
+
+    for key in map.keys() {
+        if !CONFIG_KEYS.contains(&key.as_str()) {
+            errors.push(located_error(
+                source,
+                &format!("\"{}\"", key),
+                format!("Unknown setting \"{}\"", key),
+            ));
+        }
+    }
+
+    for (section, known) in [("watchdog", WATCHDOG_KEYS), ("cache", CACHE_KEYS)] {
+        if let Some(serde_json::Value::Object(settings)) = map.get(section) {
+            for key in settings.keys() {
+                if !known.contains(&key.as_str()) {
+                    errors.push(located_error(
+                        source,
+                        &format!("\"{}\"", key),
+                        format!("Unknown setting \"{}.{}\"", section, key),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Remove invalid values so they fall back to defaults, recording why
+fn validate(config: &mut Config, source: &str, errors: &mut Vec<ConfigError>) {
+    if config.port == Some(0) {
+        errors.push(located_error(
+            source,
+            "\"port\"",
+            "port must be between 1 and 65535, using default".to_string(),
+        ));
+        config.port = None;
+    }
+
+    if let Some(host) = &config.host {
+        if host.parse::<IpAddr>().is_err() {
+            errors.push(located_error(
+                source,
+                "\"host\"",
+                format!("host \"{}\" is not an IP address, using default", host),
+            ));
+            config.host = None;
+        }
+    }
+
+    for (key, value, max) in [
+        ("workers", &mut config.workers, MAX_WORKERS),
+        ("slow_workers", &mut config.slow_workers, MAX_WORKERS),
+        ("queue_depth", &mut config.queue_depth, MAX_QUEUE_DEPTH),
+    ] {
+        if value.is_some_and(|count| count == 0 || count > max) {
+            errors.push(located_error(
+                source,
+                &format!("\"{}\"", key),
+                format!("{} must be between 1 and {}, using default", key, max),
+            ));
+            *value = None;
+        }
+    }
+
+    if let Some(level) = &config.log_level {
+        if level.parse::<LevelFilter>().is_err() {
+            errors.push(located_error(
+                source,
+                "\"log_level\"",
+                format!(
+                    "log_level \"{}\" must be one of off, error, warn, info, debug or trace",
+                    level
+                ),
+            ));
+            config.log_level = None;
+        }
+    }
+
+    if let Some(clients) = &mut config.allowed_clients {
+        clients.retain(|client| {
+            let valid = client == ANY_CLIENT || client.parse::<IpAddr>().is_ok();
+
+            if !valid {
+                errors.push(located_error(
+                    source,
+                    &format!("\"{}\"", client),
+                    format!("allowed_clients entry \"{}\" is not an IP address", client),
+                ));
+            }
+
+            valid
+        });
+    }
+
+    if config
+        .shutdown_timeout_seconds
+        .is_some_and(|timeout| timeout > MAX_SHUTDOWN_TIMEOUT_SECONDS)
+    {
+        errors.push(located_error(
+            source,
+            "\"shutdown_timeout_seconds\"",
+            format!(
+                "shutdown_timeout_seconds must be at most {}, using default",
+                MAX_SHUTDOWN_TIMEOUT_SECONDS
+            ),
+        ));
+        config.shutdown_timeout_seconds = None;
+    }
+
+    if let Some(watchdog) = &mut config.watchdog {
+        if watchdog.poll_interval_ms.is_some_and(|interval| {
+            !(MIN_WATCHDOG_INTERVAL_MS..=MAX_WATCHDOG_INTERVAL_MS).contains(&interval)
+        }) {
+            errors.push(located_error(
+                source,
+                "\"poll_interval_ms\"",
+                format!(
+                    "watchdog.poll_interval_ms must be between {} and {}, using default",
+                    MIN_WATCHDOG_INTERVAL_MS, MAX_WATCHDOG_INTERVAL_MS
+                ),
+            ));
+            watchdog.poll_interval_ms = None;
+        }
+
+        if let Some(names) = &mut watchdog.process_names {
+            names.retain(|name| !name.trim().is_empty());
+
+            if names.is_empty() {
+                errors.push(located_error(
+                    source,
+                    "\"process_names\"",
+                    "watchdog.process_names must list at least one name, using default".to_string(),
+                ));
+                watchdog.process_names = None;
+            }
+        }
+    }
+
+    if let Some(cache) = &mut config.cache {
+        if cache
+            .memory_mb
+            .is_some_and(|megabytes| megabytes > MAX_CACHE_MEMORY_MB)
+        {
+            errors.push(located_error(
+                source,
+                "\"memory_mb\"",
+                format!(
+                    "cache.memory_mb must be at most {}, using default",
+                    MAX_CACHE_MEMORY_MB
+                ),
+            ));
+            cache.memory_mb = None;
+        }
+
+        if cache
+            .disk_mb
+            .is_some_and(|megabytes| megabytes == 0 || megabytes > MAX_CACHE_DISK_MB)
+        {
+            errors.push(located_error(
+                source,
+                "\"disk_mb\"",
+                format!(
+                    "cache.disk_mb must be between 1 and {}, using default",
+                    MAX_CACHE_DISK_MB
+                ),
+            ));
+            cache.disk_mb = None;
+        }
+
+        check_directory(
+            &mut cache.disk_directory,
+            "cache.disk_directory",
+            source,
+            errors,
+        );
+    }
+
+    if config
+        .max_texture_size
+        .is_some_and(|size| !(MIN_TEXTURE_SIZE..=MAX_TEXTURE_SIZE).contains(&size))
+    {
+        errors.push(located_error(
+            source,
+            "\"max_texture_size\"",
+            format!(
+                "max_texture_size must be between {} and {}, using default",
+                MIN_TEXTURE_SIZE, MAX_TEXTURE_SIZE
+            ),
+        ));
+        config.max_texture_size = None;
+    }
+
+    check_directory(
+        &mut config.dds_output_directory,
+        "dds_output_directory",
+        source,
+        errors,
+    );
+    check_directory(&mut config.font_directory, "font_directory", source, errors);
+
+    if let Some(executables) = &mut config.allowed_executables {
+        executables.retain(|entry| {
+            let problem = if entry.name.trim().is_empty() {
+                Some("allowed_executables entry is missing a name".to_string())
+            } else if entry
+                .path
+                .as_ref()
+                .is_some_and(|path| !Path::new(path).is_absolute())
+            {
+                Some(format!(
+                    "allowed_executables entry \"{}\" path must be absolute",
+                    entry.name
+                ))
+            } else if entry
+                .sha256
+                .as_ref()
+                .is_some_and(|hash| !is_sha256_hex(hash))
+            {
+                Some(format!(
+                    "allowed_executables entry \"{}\" sha256 must be 64 hex characters",
+                    entry.name
+                ))
+            } else {
+                None
+            };
+
+            match problem {
+                Some(message) => {
+                    errors.push(located_error(
+                        source,
+                        &format!("\"{}\"", entry.name),
+                        message,
+                    ));
+                    false
+                }
+                None => true,
+            }
+        });
+    }
+}
+
+/// Clear a directory setting left empty so that its default is used, `name` being its dotted path
+fn check_directory(
+    directory: &mut Option<String>,
+    name: &str,
+    source: &str,
+    errors: &mut Vec<ConfigError>,
+) {
+    if directory
+        .as_ref()
+        .is_some_and(|directory| directory.trim().is_empty())
+    {
+        let key = name.rsplit('.').next().unwrap_or(name);
+        errors.push(located_error(
+            source,
+            &format!("\"{}\"", key),
+            format!("{} must not be empty, using default", name),
+        ));
+        *directory = None;
+    }
+}
+
+fn located_error(source: &str, needle: &str, message: String) -> ConfigError {
+    let (line, column) = match source.find(needle) {
+        Some(offset) => {
+            let before = &source[..offset];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1) + 1;
+            (Some(line), Some(column))
+        }
+        None => (None, None),
+    };
+
+    ConfigError {
+        message,
+        line,
+        column,
+    }
+}
+
+/// serde_json appends " at line X column Y" to messages, which is reported separately
+fn strip_location(err: &serde_json::Error) -> String {
+    let message = err.to_string();
+
+    match message.rfind(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn startup_settings_stay_in_effect_until_restart() {
+        let mut state = ConfigState::default();
+        state.startup = Some(state.config.resolved());
+        state.config.port = Some(41013);
+        state.config.workers = Some(2);
+
+        let effective = state.effective();
+        assert_eq!(effective.port, state.startup.as_ref().unwrap().port);
+        assert_eq!(effective.workers, state.startup.as_ref().unwrap().workers);
+        assert!(state.restart_required());
+    }
+}
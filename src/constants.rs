@@ -1,30 +1,282 @@
+use log::LevelFilter;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
 
-#[derive(Default, Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct Config {
     pub port: Option<u16>,
+    pub host: Option<String>,
+    pub allowed_clients: Option<Vec<String>>,
+    pub allowed_executables: Option<Vec<AllowedExecutable>>,
+    /// Threads serving quick requests
+    pub workers: Option<usize>,
+    /// Threads serving requests that decode images or walk directories
+    pub slow_workers: Option<usize>,
+    /// Requests each group of workers can have waiting before answering 503
+    pub queue_depth: Option<usize>,
+    /// Verbosity of the log file: off, error, warn, info, debug or trace
+    pub log_level: Option<String>,
+    pub watchdog: Option<WatchdogConfig>,
+    /// Seconds to let in-flight requests finish when shutting down
+    pub shutdown_timeout_seconds: Option<u64>,
+    pub cache: Option<CacheConfig>,
+    /// Directory /encode_dds writes into, relative to the executable unless absolute
+    pub dds_output_directory: Option<String>,
+    /// Largest width or height of an image sent to the game, larger ones are scaled down
+    pub max_texture_size: Option<u32>,
+    /// Directory /text looks in for fonts before the system's, relative to the executable unless absolute
+    pub font_directory: Option<String>,
 }
 
+/// When the server shuts itself down because the game has exited
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct WatchdogConfig {
+    /// Set to false to keep the server running without the game, e.g. during development
+    pub enabled: Option<bool>,
+    /// Names of the processes that count as the game, matched case-insensitively
+    pub process_names: Option<Vec<String>>,
+    /// Milliseconds between checks for the game
+    pub poll_interval_ms: Option<u64>,
+    /// Seconds the game may be gone before shutting down, so a restart does not stop the server
+    pub grace_period_seconds: Option<u64>,
+}
+
+/// Where converted images are kept so that repeated requests skip decoding and encoding
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct CacheConfig {
+    /// Megabytes of converted images kept in memory, 0 to disable
+    pub memory_mb: Option<u64>,
+    /// Also keep converted images on disk so they survive restarts
+    pub disk_enabled: Option<bool>,
+    /// Directory of the disk cache, relative to the executable unless absolute
+    pub disk_directory: Option<String>,
+    /// Megabytes the disk cache may use before the oldest files are removed
+    pub disk_mb: Option<u64>,
+}
+
+/// An executable permitted to be started through /run
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AllowedExecutable {
+    /// File name without directories or ".exe" extension
+    pub name: String,
+    /// Absolute path the executable must resolve to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Hex encoded SHA-256 the binary must match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl Config {
+    /// Return a copy with every unset setting replaced by its default
+    pub fn resolved(&self) -> Config {
+        Config {
+            port: Some(self.port.unwrap_or(DEFAULT_PORT)),
+            host: Some(self.host.clone().unwrap_or(DEFAULT_HOST.to_string())),
+            allowed_clients: Some(self.allowed_clients.clone().unwrap_or_default()),
+            allowed_executables: Some(self.allowed_executables.clone().unwrap_or_default()),
+            workers: Some(self.workers.unwrap_or(DEFAULT_WORKERS)),
+            slow_workers: Some(self.slow_workers.unwrap_or(DEFAULT_SLOW_WORKERS)),
+            queue_depth: Some(self.queue_depth.unwrap_or(DEFAULT_QUEUE_DEPTH)),
+            log_level: Some(
+                self.log_level
+                    .clone()
+                    .unwrap_or(DEFAULT_LOG_LEVEL.to_string()),
+            ),
+            watchdog: Some(self.watchdog.clone().unwrap_or_default().resolved()),
+            shutdown_timeout_seconds: Some(
+                self.shutdown_timeout_seconds
+                    .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECONDS),
+            ),
+            cache: Some(self.cache.clone().unwrap_or_default().resolved()),
+            dds_output_directory: Some(
+                self.dds_output_directory
+                    .clone()
+                    .unwrap_or(DEFAULT_DDS_OUTPUT_DIRECTORY.to_string()),
+            ),
+            max_texture_size: Some(self.max_texture_size.unwrap_or(DEFAULT_MAX_TEXTURE_SIZE)),
+            font_directory: Some(
+                self.font_directory
+                    .clone()
+                    .unwrap_or(DEFAULT_FONT_DIRECTORY.to_string()),
+            ),
+        }
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        self.log_level
+            .as_deref()
+            .unwrap_or(DEFAULT_LOG_LEVEL)
+            .parse()
+            .unwrap_or(LevelFilter::Info)
+    }
+}
+
+impl WatchdogConfig {
+    /// Return a copy with every unset setting replaced by its default
+    pub fn resolved(&self) -> WatchdogConfig {
+        WatchdogConfig {
+            enabled: Some(self.enabled.unwrap_or(true)),
+            process_names: Some(
+                self.process_names
+                    .clone()
+                    .unwrap_or(vec![DARKTIDE_PROCESS_NAME.to_string()]),
+            ),
+            poll_interval_ms: Some(
+                self.poll_interval_ms
+                    .unwrap_or(DEFAULT_WATCHDOG_INTERVAL_MS),
+            ),
+            grace_period_seconds: Some(
+                self.grace_period_seconds
+                    .unwrap_or(DEFAULT_WATCHDOG_GRACE_SECONDS),
+            ),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Return a copy with every unset setting replaced by its default
+    pub fn resolved(&self) -> CacheConfig {
+        CacheConfig {
+            memory_mb: Some(self.memory_mb.unwrap_or(DEFAULT_CACHE_MEMORY_MB)),
+            disk_enabled: Some(self.disk_enabled.unwrap_or(false)),
+            disk_directory: Some(
+                self.disk_directory
+                    .clone()
+                    .unwrap_or(DEFAULT_CACHE_DIRECTORY.to_string()),
+            ),
+            disk_mb: Some(self.disk_mb.unwrap_or(DEFAULT_CACHE_DISK_MB)),
+        }
+    }
+}
+
+/// Keys recognised in config.json
+pub const CONFIG_KEYS: &[&str] = &[
+    "port",
+    "host",
+    "allowed_clients",
+    "allowed_executables",
+    "workers",
+    "slow_workers",
+    "queue_depth",
+    "log_level",
+    "watchdog",
+    "shutdown_timeout_seconds",
+    "cache",
+    "dds_output_directory",
+    "max_texture_size",
+    "font_directory",
+];
+
+/// Keys recognised in the "watchdog" object of config.json
+pub const WATCHDOG_KEYS: &[&str] = &[
+    "enabled",
+    "process_names",
+    "poll_interval_ms",
+    "grace_period_seconds",
+];
+
+/// Keys recognised in the "cache" object of config.json
+pub const CACHE_KEYS: &[&str] = &["memory_mb", "disk_enabled", "disk_directory", "disk_mb"];
+
 #[derive(Deserialize)]
 pub struct RunRequest {
     pub command: String,
 }
 
-#[derive(Serialize)]
-pub struct RunResponse {
-    pub success: bool,
-    pub pid: Option<u32>,
+#[derive(Deserialize)]
+pub struct EncodeDdsRequest {
+    /// Image to encode
+    pub path: String,
+    /// bc1, bc3 or bc7
+    pub format: String,
+    /// Mark the texture as sRGB rather than linear
+    pub srgb: Option<bool>,
+    /// Generate a full chain of mipmaps, true unless given
+    pub mipmaps: Option<bool>,
+    /// fast, normal or slow
+    pub quality: Option<String>,
+    /// File to write inside the output directory, the image's name with ".dds" unless given
+    pub output: Option<String>,
 }
 
-pub fn allowed_executables() -> HashSet<String> {
-    let mut set = HashSet::new();
-    set.insert("ffplay_dt".to_string());
-    set
+/// Executables allowed without any entry in config.json
+pub fn builtin_executables() -> Vec<AllowedExecutable> {
+    vec![AllowedExecutable {
+        name: "ffplay_dt".to_string(),
+        path: None,
+        sha256: None,
+    }]
 }
 
+#[cfg(windows)]
 pub const MUTEX_NAME: &str = "Global\\DarktideLocalServerMutex";
+#[cfg(unix)]
+pub const LOCK_FILE_NAME: &str = "DarktideLocalServer.lock";
+pub const DARKTIDE_PROCESS_NAME: &str = "Darktide.exe";
 pub const DEFAULT_PORT: u16 = 41012;
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const ANY_CLIENT: &str = "*";
 pub const CONFIG_NAME: &str = "config.json";
+pub const CONFIG_POLL_INTERVAL: u64 = 1;
+pub const DEFAULT_WORKERS: usize = 4;
+pub const DEFAULT_SLOW_WORKERS: usize = 2;
+pub const DEFAULT_QUEUE_DEPTH: usize = 32;
+pub const MAX_WORKERS: usize = 64;
+pub const MAX_QUEUE_DEPTH: usize = 1024;
+pub const RETRY_AFTER_SECONDS: u64 = 1;
+pub const LOG_NAME: &str = "DarktideLocalServer.log";
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+/// Size at which the log file is rotated
+pub const LOG_MAX_BYTES: u64 = 1024 * 1024;
+/// Number of rotated log files kept alongside the current one
+pub const LOG_FILES_KEPT: usize = 3;
+pub const DEFAULT_WATCHDOG_INTERVAL_MS: u64 = 1000;
+pub const MIN_WATCHDOG_INTERVAL_MS: u64 = 100;
+pub const MAX_WATCHDOG_INTERVAL_MS: u64 = 60_000;
+pub const DEFAULT_WATCHDOG_GRACE_SECONDS: u64 = 10;
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SECONDS: u64 = 5;
+pub const MAX_SHUTDOWN_TIMEOUT_SECONDS: u64 = 300;
+/// Exit codes, so whatever started the server can tell why it stopped
+pub const EXIT_REQUESTED: i32 = 0;
+pub const EXIT_GAME_EXITED: i32 = 2;
+pub const EXIT_SIGNAL: i32 = 130;
+pub const DEFAULT_JPEG_QUALITY: u8 = 80;
+/// Largest width or height an image can be resized to
+pub const MAX_RESIZE_DIMENSION: u32 = 8192;
+pub const DEFAULT_CACHE_MEMORY_MB: u64 = 64;
+pub const MAX_CACHE_MEMORY_MB: u64 = 4096;
+pub const DEFAULT_CACHE_DIRECTORY: &str = "cache";
+pub const DEFAULT_CACHE_DISK_MB: u64 = 512;
+pub const MAX_CACHE_DISK_MB: u64 = 65_536;
+/// Clients may keep file responses but must revalidate them, since files can change at any time
+pub const CACHE_CONTROL: &str = "no-cache";
+pub const DEFAULT_DDS_OUTPUT_DIRECTORY: &str = "dds_output";
+/// Separates the paths of an atlas's sprites, as it cannot appear in Windows paths
+pub const ATLAS_PATH_SEPARATOR: char = '|';
+pub const MAX_ATLAS_SPRITES: usize = 256;
+pub const DEFAULT_ATLAS_SIZE: u32 = 4096;
+/// Transparent pixels left around every sprite of an atlas or frame of a sprite sheet, so that
+/// filtering does not bleed between them
+pub const DEFAULT_SPRITE_PADDING: u32 = 2;
+pub const MAX_SPRITE_PADDING: u32 = 64;
+/// Largest width or height of an atlas or sprite sheet
+pub const MAX_SPRITE_SHEET_SIZE: u32 = 8192;
+/// Separates the steps of an `ops` query parameter
+pub const OPERATION_SEPARATOR: char = '|';
+pub const MAX_OPERATIONS: usize = 16;
+pub const MAX_ANIMATION_FRAMES: u32 = 256;
+pub const DEFAULT_MAX_TEXTURE_SIZE: u32 = 8192;
+pub const MIN_TEXTURE_SIZE: u32 = 64;
+pub const MAX_TEXTURE_SIZE: u32 = 16_384;
+pub const MAX_IMAGE_TILES: u32 = 1024;
+pub const DEFAULT_PALETTE_COLOURS: u32 = 256;
+pub const MIN_PALETTE_COLOURS: u32 = 2;
+pub const MAX_PALETTE_COLOURS: u32 = 256;
+pub const DEFAULT_FONT_DIRECTORY: &str = "fonts";
+pub const DEFAULT_TEXT_SIZE: u32 = 32;
+pub const MIN_TEXT_SIZE: u32 = 4;
+pub const MAX_TEXT_SIZE: u32 = 512;
+pub const MAX_TEXT_LENGTH: usize = 4096;
+pub const MAX_TEXT_OUTLINE: u32 = 8;
 pub const SUCCESS: &str = "success";
 pub const PID: &str = "pid";
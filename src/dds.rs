@@ -0,0 +1,440 @@
+use ddsfile::{Caps2, Dds, FourCC, MiscFlag};
+use image::{imageops, imageops::FilterType, RgbaImage};
+use image_dds::{
+    dds_from_image, dds_image_format, decode_surface_rgba8, mip_dimension, CreateDdsError,
+    ImageFormat, Mipmaps, Quality, Surface,
+};
+use std::{collections::HashMap, fs::File, io::Read};
+
+use crate::constants::EncodeDdsRequest;
+use crate::errors::ServerError;
+
+/// Cubemap faces in the order they are stored
+const FACE_NAMES: [&str; 6] = ["+x", "-x", "+y", "-y", "+z", "-z"];
+
+/// Legacy header flags marking which faces a cubemap stores, in the same order
+const FACE_FLAGS: [Caps2; 6] = [
+    Caps2::CUBEMAP_POSITIVEX,
+    Caps2::CUBEMAP_NEGATIVEX,
+    Caps2::CUBEMAP_POSITIVEY,
+    Caps2::CUBEMAP_NEGATIVEY,
+    Caps2::CUBEMAP_POSITIVEZ,
+    Caps2::CUBEMAP_NEGATIVEZ,
+];
+
+/// Bytes taken by the magic number, the header and the DX10 header
+const HEADER_LENGTH: u64 = 4 + 124 + 20;
+
+/// Width and height in pixels of the blocks BCn formats compress
+const BLOCK_SIZE: u32 = 4;
+
+/// Column and row of each face in a horizontal cross, which is 4 faces wide and 3 tall
+const CROSS_POSITIONS: [(u32, u32); 6] = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+
+/// Compressed format and settings for writing a texture, read from an `EncodeDdsRequest`
+pub struct DdsEncoding {
+    pub format: ImageFormat,
+    pub quality: Quality,
+    pub mipmaps: Mipmaps,
+}
+
+/// A texture's compressed surfaces, decoded to RGBA8 one image at a time
+pub struct DdsTexture<'a> {
+    surface: Surface<&'a [u8]>,
+    cubemap: bool,
+}
+
+/// Which image of a texture to return, read from `mip`, `layer` and `face` query parameters
+#[derive(Default)]
+pub struct Selection {
+    pub mip: u32,
+    pub layer: u32,
+    pub face: Option<u32>,
+}
+
+impl Selection {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Selection, ServerError> {
+        let face = match params.get("face") {
+            None => None,
+            Some(value) => Some(parse_face(value)?),
+        };
+
+        Ok(Selection {
+            mip: index_param(params, "mip")?,
+            layer: index_param(params, "layer")?,
+            face,
+        })
+    }
+}
+
+impl DdsEncoding {
+    pub fn from_request(request: &EncodeDdsRequest) -> Result<DdsEncoding, ServerError> {
+        let srgb = request.srgb.unwrap_or(false);
+
+        let format = match (request.format.to_lowercase().as_str(), srgb) {
+            ("bc1", false) => ImageFormat::BC1Unorm,
+            ("bc1", true) => ImageFormat::BC1Srgb,
+            ("bc3", false) => ImageFormat::BC3Unorm,
+            ("bc3", true) => ImageFormat::BC3Srgb,
+            ("bc7", false) => ImageFormat::BC7Unorm,
+            ("bc7", true) => ImageFormat::BC7Srgb,
+            _ => return Err(ServerError::invalid_parameter("format", &request.format)),
+        };
+
+        let quality = match request.quality.as_deref().map(str::to_lowercase) {
+            None => Quality::Normal,
+            Some(value) => match value.as_str() {
+                "fast" => Quality::Fast,
+                "normal" => Quality::Normal,
+                "slow" => Quality::Slow,
+                _ => return Err(ServerError::invalid_parameter("quality", &value)),
+            },
+        };
+
+        let mipmaps = match request.mipmaps {
+            Some(false) => Mipmaps::Disabled,
+            _ => Mipmaps::GeneratedAutomatic,
+        };
+
+        Ok(DdsEncoding {
+            format,
+            quality,
+            mipmaps,
+        })
+    }
+
+    /// Compress an image into a DDS file, padded up to whole 4x4 blocks if it is not already
+    pub fn encode(&self, image: &RgbaImage) -> Result<Dds, ServerError> {
+        let padded = pad_to_blocks(image);
+        let image = padded.as_ref().unwrap_or(image);
+
+        dds_from_image(image, self.format, self.quality, self.mipmaps).map_err(|err| match err {
+            CreateDdsError::CompressSurface(err) => {
+                ServerError::unprocessable(format!("Could not compress image: {}", err))
+            }
+            CreateDdsError::Dds(err) => {
+                ServerError::internal(format!("Could not create DDS file: {}", err))
+            }
+        })
+    }
+}
+
+impl<'a> DdsTexture<'a> {
+    pub fn new(dds: &'a Dds) -> Result<DdsTexture<'a>, ServerError> {
+        let Some(image_format) = dds_image_format(dds) else {
+            return Err(ServerError::unsupported_media_type(format!(
+                "Unsupported DDS format: {}",
+                format_name(dds)
+            )));
+        };
+
+        // Legacy cubemaps store only the faces they flag, so one with faces missing is read as a
+        // plain array of the faces it has
+        let layers = match &dds.header10 {
+            Some(_) if is_cubemap(dds) => array_size(dds) * 6,
+            Some(_) => array_size(dds),
+            None => (cube_faces(dds).len() as u32).max(1),
+        };
+
+        Ok(DdsTexture {
+            surface: Surface {
+                width: dds.get_width(),
+                height: dds.get_height(),
+                depth: dds.get_depth(),
+                layers,
+                mipmaps: dds.get_num_mipmap_levels().max(1),
+                image_format,
+                data: &dds.data,
+            },
+            cubemap: is_cubemap(dds) && layers % 6 == 0,
+        })
+    }
+
+    /// Number of array layers, counting each cube of a cubemap array once
+    pub fn layers(&self) -> u32 {
+        if self.cubemap {
+            self.surface.layers / 6
+        } else {
+            self.surface.layers
+        }
+    }
+
+    /// Return one mip of one layer, or of one face for cubemaps (the first face if not given)
+    ///
+    /// Depth slices of volume textures are stacked from top to bottom.
+    pub fn image(&self, selection: &Selection) -> Result<RgbaImage, ServerError> {
+        self.check_range(selection.mip, selection.layer)?;
+
+        let face = match (selection.face, self.cubemap) {
+            (Some(_), false) => {
+                return Err(ServerError::bad_request(
+                    "face can only be used with cubemaps storing all six faces",
+                ))
+            }
+            (face, true) => face.unwrap_or(0),
+            (None, false) => 0,
+        };
+
+        let surface_layer = if self.cubemap {
+            selection.layer * 6 + face
+        } else {
+            selection.layer
+        };
+
+        self.surface_image(surface_layer, selection.mip)
+    }
+
+    /// Return all six faces of one mip of a cubemap laid out as a horizontal cross, no wider than
+    /// `limit`
+    ///
+    /// Faces are scaled down one at a time before they are placed, so the full size cross is
+    /// never held in memory.
+    pub fn cross(&self, mip: u32, layer: u32, limit: u32) -> Result<RgbaImage, ServerError> {
+        if !self.cubemap {
+            return Err(ServerError::unprocessable(
+                "Texture is not a cubemap with all six faces",
+            ));
+        }
+        self.check_range(mip, layer)?;
+
+        let size = mip_dimension(self.surface.width, mip).min(limit / 4).max(1);
+        let mut cross = RgbaImage::new(size * 4, size * 3);
+
+        for (face, (column, row)) in CROSS_POSITIONS.iter().enumerate() {
+            let mut image = self.surface_image(layer * 6 + face as u32, mip)?;
+            if image.dimensions() != (size, size) {
+                image = imageops::resize(&image, size, size, FilterType::Lanczos3);
+            }
+            imageops::replace(
+                &mut cross,
+                &image,
+                i64::from(column * size),
+                i64::from(row * size),
+            );
+        }
+
+        Ok(cross)
+    }
+
+    fn check_range(&self, mip: u32, layer: u32) -> Result<(), ServerError> {
+        if mip >= self.surface.mipmaps {
+            return Err(ServerError::out_of_range(format!(
+                "mip {} is out of range, the texture has {} mip levels",
+                mip, self.surface.mipmaps
+            )));
+        }
+
+        if layer >= self.layers() {
+            return Err(ServerError::out_of_range(format!(
+                "layer {} is out of range, the texture has {} layers",
+                layer,
+                self.layers()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Decode a single mip of a single surface, leaving the rest of the texture compressed
+    fn surface_image(&self, layer: u32, mip: u32) -> Result<RgbaImage, ServerError> {
+        let missing = || ServerError::unprocessable("DDS file is missing image data");
+        let data = self.surface.get(layer, mip).ok_or_else(missing)?;
+
+        let width = mip_dimension(self.surface.width, mip);
+        let height = mip_dimension(self.surface.height, mip);
+        let depth = mip_dimension(self.surface.depth, mip);
+
+        let decoded = decode_surface_rgba8(Surface {
+            width,
+            height,
+            depth,
+            layers: 1,
+            mipmaps: 1,
+            image_format: self.surface.image_format,
+            data,
+        })
+        .map_err(|err| ServerError::unprocessable(format!("Could not decode DDS file: {}", err)))?;
+
+        RgbaImage::from_raw(width, height * depth, decoded.data).ok_or_else(missing)
+    }
+}
+
+/// Extend an image to whole blocks by repeating its last column and row, which keeps the colours
+/// of the edge blocks close to the image's own, or return `None` if it needs no padding
+fn pad_to_blocks(image: &RgbaImage) -> Option<RgbaImage> {
+    let (width, height) = image.dimensions();
+    let padded_width = (width + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+    let padded_height = (height + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+
+    if (padded_width, padded_height) == (width, height) {
+        return None;
+    }
+
+    Some(RgbaImage::from_fn(padded_width, padded_height, |x, y| {
+        *image.get_pixel(x.min(width - 1), y.min(height - 1))
+    }))
+}
+
+/// Open and parse the DDS file at `path`
+pub fn read_dds(path: &str) -> Result<Dds, ServerError> {
+    let mut file = File::open(path).map_err(|err| ServerError::from_io(&err, path))?;
+
+    Dds::read(&mut file)
+        .map_err(|err| ServerError::unprocessable(format!("Invalid DDS file: {}", err)))
+}
+
+/// Parse only the headers of the DDS file at `path`, leaving `data` empty or truncated
+pub fn read_dds_header(path: &str) -> Result<Dds, ServerError> {
+    let file = File::open(path).map_err(|err| ServerError::from_io(&err, path))?;
+
+    Dds::read(file.take(HEADER_LENGTH))
+        .map_err(|err| ServerError::unprocessable(format!("Invalid DDS file: {}", err)))
+}
+
+/// Whether a texture is a cubemap, flagged in either the DX10 or the legacy header
+pub fn is_cubemap(dds: &Dds) -> bool {
+    dds.header10
+        .as_ref()
+        .is_some_and(|header10| header10.misc_flag.contains(MiscFlag::TEXTURECUBE))
+        || dds.header.caps2.contains(Caps2::CUBEMAP)
+}
+
+/// Names of the faces stored by a cubemap, which legacy files may leave incomplete
+pub fn cube_faces(dds: &Dds) -> Vec<&'static str> {
+    if !is_cubemap(dds) {
+        return Vec::new();
+    }
+
+    // DX10 cubemaps always store all six faces
+    if dds.header10.is_some() {
+        return FACE_NAMES.to_vec();
+    }
+
+    FACE_NAMES
+        .iter()
+        .zip(FACE_FLAGS)
+        .filter(|(_, flag)| dds.header.caps2.contains(*flag))
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Number of array layers, counting each cube of a cubemap array once
+pub fn array_size(dds: &Dds) -> u32 {
+    match &dds.header10 {
+        Some(header10) => header10.array_size,
+        None => 1,
+    }
+}
+
+/// Name of the texture's format, from the DX10 header or else the legacy D3D format or FourCC
+pub fn format_name(dds: &Dds) -> String {
+    if let Some(header10) = &dds.header10 {
+        format!("{:?}", header10.dxgi_format)
+    } else if let Some(format) = dds.get_d3d_format() {
+        format!("{:?}", format)
+    } else if let Some(fourcc) = &dds.header.spf.fourcc {
+        fourcc_name(fourcc)
+    } else {
+        "unknown".to_string()
+    }
+}
+
+/// Four character codes as text, e.g. "DXT5", or as a number for the D3D format codes
+pub fn fourcc_name(fourcc: &FourCC) -> String {
+    let bytes = fourcc.0.to_le_bytes();
+
+    if bytes.iter().all(|byte| byte.is_ascii_graphic()) {
+        String::from_utf8_lossy(&bytes).into_owned()
+    } else {
+        fourcc.0.to_string()
+    }
+}
+
+fn index_param(params: &HashMap<String, String>, name: &str) -> Result<u32, ServerError> {
+    match params.get(name) {
+        None => Ok(0),
+        Some(value) => value
+            .parse()
+            .map_err(|_| ServerError::invalid_parameter(name, value)),
+    }
+}
+
+/// Accept a face index from 0 to 5 or a name such as "+x" or "nz"
+fn parse_face(value: &str) -> Result<u32, ServerError> {
+    // A "+" left unencoded in the query string arrives as a space
+    let name = value
+        .to_lowercase()
+        .replace([' ', 'p'], "+")
+        .replace('n', "-");
+
+    if let Some(index) = FACE_NAMES.iter().position(|face| *face == name) {
+        return Ok(index as u32);
+    }
+
+    match value.parse::<u32>() {
+        Ok(index) if index < 6 => Ok(index),
+        Ok(_) => Err(ServerError::out_of_range(format!(
+            "face {} is out of range, cubemaps have 6 faces",
+            value
+        ))),
+        Err(_) => Err(ServerError::invalid_parameter("face", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddsfile::{D3DFormat, NewD3dParams};
+
+    fn legacy_cubemap(faces: Caps2) -> Dds {
+        let mut dds = Dds::new_d3d(NewD3dParams {
+            height: 8,
+            width: 8,
+            depth: None,
+            format: D3DFormat::DXT1,
+            mipmap_levels: Some(1),
+            caps2: Some(Caps2::CUBEMAP | faces),
+        })
+        .unwrap();
+        // Four 8 byte blocks for each face
+        dds.data = vec![0; 32 * faces.bits().count_ones() as usize];
+        dds
+    }
+
+    #[test]
+    fn encoding_pads_to_whole_blocks() {
+        let encoding = DdsEncoding {
+            format: ImageFormat::BC1Unorm,
+            quality: Quality::Fast,
+            mipmaps: Mipmaps::Disabled,
+        };
+        let dds = encoding.encode(&RgbaImage::new(5, 3)).unwrap();
+
+        assert_eq!((dds.get_width(), dds.get_height()), (8, 4));
+    }
+
+    #[test]
+    fn legacy_cubemap_has_one_layer_of_six_faces() {
+        let dds = legacy_cubemap(FACE_FLAGS.into_iter().collect());
+        let texture = DdsTexture::new(&dds).unwrap();
+
+        assert_eq!(texture.layers(), 1);
+        let selection = Selection {
+            face: Some(5),
+            ..Selection::default()
+        };
+        assert_eq!(texture.image(&selection).unwrap().dimensions(), (8, 8));
+        assert_eq!(texture.cross(0, 0, 1024).unwrap().dimensions(), (32, 24));
+        assert_eq!(texture.cross(0, 0, 16).unwrap().dimensions(), (16, 12));
+    }
+
+    #[test]
+    fn partial_legacy_cubemap_is_read_as_an_array() {
+        let dds = legacy_cubemap(Caps2::CUBEMAP_POSITIVEX | Caps2::CUBEMAP_NEGATIVEY);
+        let texture = DdsTexture::new(&dds).unwrap();
+
+        assert_eq!(texture.layers(), 2);
+        assert!(texture.image(&Selection::default()).is_ok());
+        assert!(texture.cross(0, 0, 1024).is_err());
+    }
+}
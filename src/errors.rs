@@ -0,0 +1,124 @@
+use serde::Serialize;
+use std::{
+    fmt,
+    io::{Error as IoError, ErrorKind},
+};
+use tiny_http::{ResponseBox, StatusCode};
+
+use crate::utilities::json_response_with_status;
+
+/// What every handler returns, with failures rendered as a JSON error envelope
+pub type HandlerResult = Result<ResponseBox, ServerError>;
+
+/// A failed request, rendered as `{"error": {"code": "...", "message": "..."}}`
+#[derive(Debug)]
+pub struct ServerError {
+    pub status: u16,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorEnvelope<'a> {
+    error: ErrorBody<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+impl ServerError {
+    pub fn new(status: u16, code: &'static str, message: impl Into<String>) -> Self {
+        ServerError {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, "bad_request", message)
+    }
+
+    pub fn missing_parameter(name: &str) -> Self {
+        Self::new(
+            400,
+            "missing_parameter",
+            format!("Missing \"{}\" query parameter", name),
+        )
+    }
+
+    pub fn invalid_parameter(name: &str, value: &str) -> Self {
+        Self::new(
+            400,
+            "invalid_parameter",
+            format!("Invalid value \"{}\" for \"{}\"", value, name),
+        )
+    }
+
+    pub fn out_of_range(message: impl Into<String>) -> Self {
+        Self::new(400, "out_of_range", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(403, "forbidden", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(404, "not_found", message)
+    }
+
+    pub fn method_not_allowed(message: impl Into<String>) -> Self {
+        Self::new(405, "method_not_allowed", message)
+    }
+
+    pub fn unsupported_media_type(message: impl Into<String>) -> Self {
+        Self::new(415, "unsupported_media_type", message)
+    }
+
+    pub fn unprocessable(message: impl Into<String>) -> Self {
+        Self::new(422, "unprocessable_content", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(500, "internal_error", message)
+    }
+
+    pub fn service_unavailable(message: impl Into<String>) -> Self {
+        Self::new(503, "service_unavailable", message)
+    }
+
+    /// Map a failure to open or read a file, naming the file in the message
+    pub fn from_io(err: &IoError, path: &str) -> Self {
+        match err.kind() {
+            ErrorKind::NotFound => {
+                Self::new(404, "file_not_found", format!("File not found: {}", path))
+            }
+            ErrorKind::PermissionDenied => Self::new(
+                403,
+                "permission_denied",
+                format!("Permission denied: {}", path),
+            ),
+            _ => Self::internal(format!("Could not read {}: {}", path, err)),
+        }
+    }
+
+    pub fn into_response(self) -> ResponseBox {
+        let envelope = ErrorEnvelope {
+            error: ErrorBody {
+                code: self.code,
+                message: &self.message,
+            },
+        };
+
+        json_response_with_status(StatusCode(self.status), &envelope)
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.status, self.code, self.message)
+    }
+}
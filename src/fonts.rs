@@ -0,0 +1,328 @@
+use ab_glyph::{FontArc, FontVec};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+use ttf_parser::{fonts_in_collection, name_id, Face};
+
+use crate::constants::DEFAULT_FONT_DIRECTORY;
+use crate::errors::ServerError;
+use crate::utilities::configured_directory;
+use crate::CONFIG;
+
+const FONT_EXTENSIONS: &[&str] = &["ttf", "otf", "ttc", "otc"];
+
+/// Levels of subdirectories searched, as Linux keeps fonts in a tree by foundry
+const MAX_FONT_DIRECTORY_DEPTH: usize = 4;
+
+/// How long a scan of the font directories is reused before looking for new or changed files
+const FONT_SCAN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Parsed fonts kept in memory, as CJK fonts can be tens of megabytes
+const MAX_LOADED_FONTS: usize = 8;
+
+/// Subfamilies preferred when a family has several styles
+const REGULAR_STYLES: &[&str] = &["regular", "book", "normal", "roman"];
+
+/// A font file, or one face of a font collection
+#[derive(Clone, PartialEq)]
+pub struct FontSource {
+    pub path: PathBuf,
+    pub index: u32,
+}
+
+/// Names of one face of a font file, lowercased for matching
+struct FaceNames {
+    index: u32,
+    families: Vec<String>,
+    subfamily: String,
+    full_name: String,
+}
+
+struct IndexedFile {
+    modified: SystemTime,
+    faces: Vec<FaceNames>,
+}
+
+/// Font files found in the searched directories and when each was last changed
+struct FontScan {
+    directories: Vec<PathBuf>,
+    scanned: Instant,
+    files: Arc<Vec<(PathBuf, SystemTime)>>,
+}
+
+struct LoadedFont {
+    source: FontSource,
+    modified: SystemTime,
+    font: FontArc,
+}
+
+lazy_static! {
+    /// Latest scan of the font directories, so requests don't walk them every time
+    static ref FONT_SCAN: Mutex<Option<FontScan>> = Mutex::new(None);
+    /// Names of every font file seen so far, reread only when a file changes
+    static ref FONT_INDEX: Mutex<HashMap<PathBuf, IndexedFile>> = Mutex::new(HashMap::new());
+    /// Most recently used fonts last
+    static ref LOADED_FONTS: Mutex<Vec<LoadedFont>> = Mutex::new(Vec::new());
+}
+
+/// Return the configured font directory, relative to the executable unless absolute
+pub fn font_directory() -> Result<PathBuf, ServerError> {
+    let config = &CONFIG.read().unwrap().config;
+
+    configured_directory(config.font_directory.as_deref(), DEFAULT_FONT_DIRECTORY)
+        .map_err(|err| ServerError::internal(format!("Could not find font directory: {}", err)))
+}
+
+/// Find a font by file name, full name (e.g. "Arial Bold") or family, preferring its regular style
+///
+/// The font directory is searched before the system's, so fonts placed there take precedence.
+pub fn find_family(family: &str) -> Result<FontSource, ServerError> {
+    let wanted = family.trim().to_lowercase();
+    let files = font_files()?;
+
+    // File names need no parsing, so they are tried first
+    if let Some((path, _)) = files.iter().find(|(path, _)| {
+        path.file_stem()
+            .is_some_and(|stem| stem.to_string_lossy().to_lowercase() == wanted)
+    }) {
+        return Ok(FontSource {
+            path: path.clone(),
+            index: 0,
+        });
+    }
+
+    let stale: Vec<(PathBuf, SystemTime)> = {
+        let index = lock(&FONT_INDEX);
+        files
+            .iter()
+            .filter(|(path, modified)| index.get(path).map(|file| file.modified) != Some(*modified))
+            .cloned()
+            .collect()
+    };
+
+    // Parsing can take a while, so other requests may use the index in the meantime
+    let parsed: Vec<(PathBuf, IndexedFile)> = stale
+        .into_iter()
+        .map(|(path, modified)| {
+            let faces = read_face_names(&path).unwrap_or_default();
+            (path, IndexedFile { modified, faces })
+        })
+        .collect();
+
+    let mut index = lock(&FONT_INDEX);
+    index.extend(parsed);
+    let mut family_match = None;
+
+    for (path, _) in files.iter() {
+        let Some(file) = index.get(path) else {
+            continue;
+        };
+
+        for face in &file.faces {
+            let source = FontSource {
+                path: path.clone(),
+                index: face.index,
+            };
+
+            if face.full_name == wanted {
+                return Ok(source);
+            }
+
+            if face.families.contains(&wanted) {
+                if REGULAR_STYLES.contains(&face.subfamily.as_str()) {
+                    return Ok(source);
+                }
+                family_match.get_or_insert(source);
+            }
+        }
+    }
+
+    family_match.ok_or_else(|| ServerError::not_found(format!("Font not found: {}", family)))
+}
+
+/// Return the first font in the font directory, used when a request names none
+pub fn default_font() -> Result<FontSource, ServerError> {
+    let directory = font_directory()?;
+    let mut files = Vec::new();
+    collect_font_files(&directory, 0, &mut files);
+
+    files
+        .into_iter()
+        .next()
+        .map(|path| FontSource { path, index: 0 })
+        .ok_or_else(|| {
+            ServerError::not_found(format!(
+                "No fonts found in {}, pass a font or family",
+                directory.display()
+            ))
+        })
+}
+
+/// Load a font, reusing it while the file is unchanged
+pub fn load_font(source: &FontSource) -> Result<FontArc, ServerError> {
+    let display = source.path.display().to_string();
+    let modified = fs::metadata(&source.path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|err| ServerError::from_io(&err, &display))?;
+
+    let mut loaded = lock(&LOADED_FONTS);
+    if let Some(position) = loaded
+        .iter()
+        .position(|font| font.source == *source && font.modified == modified)
+    {
+        let font = loaded.remove(position);
+        let arc = font.font.clone();
+        loaded.push(font);
+        return Ok(arc);
+    }
+    drop(loaded);
+
+    let bytes = fs::read(&source.path).map_err(|err| ServerError::from_io(&err, &display))?;
+    let font = FontVec::try_from_vec_and_index(bytes, source.index)
+        .map(FontArc::new)
+        .map_err(|_| ServerError::unsupported_media_type(format!("Invalid font: {}", display)))?;
+
+    let mut loaded = lock(&LOADED_FONTS);
+    loaded.retain(|font| font.source != *source);
+    if loaded.len() >= MAX_LOADED_FONTS {
+        loaded.remove(0);
+    }
+    loaded.push(LoadedFont {
+        source: source.clone(),
+        modified,
+        font: font.clone(),
+    });
+
+    Ok(font)
+}
+
+/// Lock a cache, carrying on with its contents if a request panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Return every font file in the font directory and then the system's, rescanning periodically
+fn font_files() -> Result<Arc<Vec<(PathBuf, SystemTime)>>, ServerError> {
+    let directories: Vec<PathBuf> = std::iter::once(font_directory()?)
+        .chain(system_font_directories())
+        .collect();
+
+    if let Some(scan) = lock(&FONT_SCAN).as_ref() {
+        if scan.directories == directories && scan.scanned.elapsed() < FONT_SCAN_INTERVAL {
+            return Ok(scan.files.clone());
+        }
+    }
+
+    let mut paths = Vec::new();
+    for directory in &directories {
+        collect_font_files(directory, 0, &mut paths);
+    }
+    let files: Arc<Vec<(PathBuf, SystemTime)>> = Arc::new(
+        paths
+            .into_iter()
+            .filter_map(|path| modified_time(&path).map(|modified| (path, modified)))
+            .collect(),
+    );
+
+    *lock(&FONT_SCAN) = Some(FontScan {
+        directories,
+        scanned: Instant::now(),
+        files: files.clone(),
+    });
+
+    Ok(files)
+}
+
+#[cfg(windows)]
+fn system_font_directories() -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+    if let Some(windows) = std::env::var_os("WINDIR") {
+        directories.push(Path::new(&windows).join("Fonts"));
+    }
+    // Fonts installed for the current user only
+    if let Some(local) = std::env::var_os("LOCALAPPDATA") {
+        directories.push(Path::new(&local).join("Microsoft\\Windows\\Fonts"));
+    }
+    directories
+}
+
+#[cfg(unix)]
+fn system_font_directories() -> Vec<PathBuf> {
+    let mut directories = vec![
+        PathBuf::from("/usr/share/fonts"),
+        PathBuf::from("/usr/local/share/fonts"),
+        PathBuf::from("/Library/Fonts"),
+        PathBuf::from("/System/Library/Fonts"),
+    ];
+    if let Some(home) = std::env::var_os("HOME") {
+        directories.push(Path::new(&home).join(".local/share/fonts"));
+        directories.push(Path::new(&home).join(".fonts"));
+    }
+    directories
+}
+
+/// Add the font files in `directory` and its subdirectories to `files`, sorted by path
+fn collect_font_files(directory: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            if depth < MAX_FONT_DIRECTORY_DEPTH {
+                collect_font_files(&path, depth + 1, files);
+            }
+        } else if path.extension().is_some_and(|extension| {
+            FONT_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str())
+        }) {
+            files.push(path);
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Read the names of every face in a font file or collection
+fn read_face_names(path: &Path) -> Option<Vec<FaceNames>> {
+    let data = fs::read(path).ok()?;
+    let count = fonts_in_collection(&data).unwrap_or(1);
+
+    Some(
+        (0..count)
+            .filter_map(|index| {
+                let face = Face::parse(&data, index).ok()?;
+                let name = |id: u16| {
+                    face.names()
+                        .into_iter()
+                        .filter(|name| name.name_id == id)
+                        .find_map(|name| name.to_string())
+                        .map(|name| name.to_lowercase())
+                };
+
+                Some(FaceNames {
+                    index,
+                    families: [name_id::TYPOGRAPHIC_FAMILY, name_id::FAMILY]
+                        .into_iter()
+                        .filter_map(&name)
+                        .collect(),
+                    subfamily: name(name_id::TYPOGRAPHIC_SUBFAMILY)
+                        .or_else(|| name(name_id::SUBFAMILY))
+                        .unwrap_or_default(),
+                    full_name: name(name_id::FULL_NAME).unwrap_or_default(),
+                })
+            })
+            .collect(),
+    )
+}
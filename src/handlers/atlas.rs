@@ -0,0 +1,53 @@
+use image::DynamicImage;
+use tiny_http::{Request, StatusCode};
+
+use crate::atlas::AtlasRequest;
+use crate::cache::{cached_conversion, CacheKey};
+use crate::conditional::{is_not_modified, not_modified_response, with_key_validators};
+use crate::errors::HandlerResult;
+use crate::transform::{encode, Encoding, OutputFormat};
+use crate::utilities::{bytes_response, json_response_with_status, query_params};
+
+/// Parameters that change the atlas image
+const ATLAS_PARAMS: &[&str] = &[
+    "padding",
+    "max_size",
+    "format",
+    "quality",
+    "compression",
+    "background",
+];
+
+/// Pack several images and DDS textures into one atlas image
+pub fn handle_atlas_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let atlas = AtlasRequest::from_params(&params)?;
+    let encoding = Encoding::from_params(&params)?.with_default_format(OutputFormat::Png);
+
+    let key = CacheKey::for_files("atlas", &atlas.paths, &params, ATLAS_PARAMS);
+    if let Some(key) = &key {
+        if is_not_modified(request, key) {
+            return Ok(not_modified_response(key));
+        }
+    }
+
+    let converted = cached_conversion(key.clone(), || {
+        let layout = atlas.layout()?;
+        let image = atlas.render(&layout)?;
+
+        encode(&DynamicImage::ImageRgba8(image), &encoding, None)
+    })?;
+
+    Ok(with_key_validators(
+        bytes_response(converted.bytes, &converted.mime_type),
+        &key,
+    ))
+}
+
+/// Return where each sprite is placed in the atlas built from the same parameters
+pub fn handle_atlas_map_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let atlas = AtlasRequest::from_params(&params)?;
+
+    Ok(json_response_with_status(StatusCode(200), &atlas.layout()?))
+}
@@ -0,0 +1,23 @@
+use serde_json::json;
+use std::path::Path;
+use tiny_http::{Request, StatusCode};
+
+use crate::cache::{purge, stats};
+use crate::errors::HandlerResult;
+use crate::utilities::{json_response_with_status, query_params};
+
+/// Return hit and miss counts and the size of each cache tier
+pub fn handle_cache_request() -> HandlerResult {
+    Ok(json_response_with_status(StatusCode(200), &stats()))
+}
+
+/// Empty the cache, or only remove conversions of the file at the `path` query parameter
+pub fn handle_cache_purge_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let removed = purge(params.get("path").map(Path::new));
+
+    Ok(json_response_with_status(
+        StatusCode(200),
+        &json!({ "removed": removed }),
+    ))
+}
@@ -0,0 +1,30 @@
+use serde::Serialize;
+use tiny_http::StatusCode;
+
+use crate::config::ConfigError;
+use crate::constants::Config;
+use crate::errors::HandlerResult;
+use crate::utilities::json_response_with_status;
+use crate::CONFIG;
+
+#[derive(Serialize)]
+struct ConfigResponse<'a> {
+    path: String,
+    settings: Config,
+    errors: &'a [ConfigError],
+    restart_required: bool,
+}
+
+/// Return the effective settings and any problems found in config.json
+pub fn handle_config_request() -> HandlerResult {
+    let state = CONFIG.read().unwrap();
+
+    let response_data = ConfigResponse {
+        path: state.path.to_string_lossy().to_string(),
+        settings: state.effective(),
+        errors: &state.errors,
+        restart_required: state.restart_required(),
+    };
+
+    Ok(json_response_with_status(StatusCode(200), &response_data))
+}
@@ -0,0 +1,51 @@
+use image::DynamicImage;
+use tiny_http::Request;
+
+use crate::cache::{cached_conversion, CacheKey};
+use crate::conditional::{is_not_modified, not_modified_response, with_key_validators};
+use crate::dds::{read_dds, DdsTexture, Selection};
+use crate::errors::HandlerResult;
+use crate::transform::{encode, max_texture_size, Encoding, OutputFormat};
+use crate::utilities::{bytes_response, query_params, required_param};
+
+/// Return all six faces of a DDS cubemap laid out as a horizontal cross
+pub fn handle_dds_cubemap_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+
+    let path_param = required_param(&params, "path")?;
+    let selection = Selection::from_params(&params)?;
+    let encoding = Encoding::from_params(&params)?.with_default_format(OutputFormat::Png);
+
+    let limit = max_texture_size();
+    let kind = format!("dds_cubemap;max_texture_size={}", limit);
+    let key = CacheKey::new(
+        &kind,
+        path_param,
+        &params,
+        &[
+            "format",
+            "quality",
+            "compression",
+            "background",
+            "mip",
+            "layer",
+        ],
+    );
+    if let Some(key) = &key {
+        if is_not_modified(request, key) {
+            return Ok(not_modified_response(key));
+        }
+    }
+
+    let converted = cached_conversion(key.clone(), || {
+        let dds = read_dds(path_param)?;
+        let image = DdsTexture::new(&dds)?.cross(selection.mip, selection.layer, limit)?;
+
+        encode(&DynamicImage::ImageRgba8(image), &encoding, None)
+    })?;
+
+    Ok(with_key_validators(
+        bytes_response(converted.bytes, &converted.mime_type),
+        &key,
+    ))
+}
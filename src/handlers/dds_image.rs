@@ -1,52 +1,57 @@
-use ddsfile::Dds;
-use image::ImageOutputFormat;
-use image_dds::image_from_dds;
-use mime_guess::from_ext;
-use std::fs::File;
-use std::io::Cursor;
-use tiny_http::{Header, Request, Response, StatusCode};
-use url::form_urlencoded;
-
-pub fn handle_dds_image_request(request: &Request) -> Option<Response<Cursor<Vec<u8>>>> {
-    let query_part = request.url().split('?').nth(1)?;
-
-    let params = form_urlencoded::parse(query_part.as_bytes())
-        .map(|(key, value)| (key.to_string(), value.to_string()))
-        .collect::<Vec<(String, String)>>();
-
-    let path_param = params.iter().find(|&&(ref key, _)| key == "path")?;
-    let format_param = params
-        .iter()
-        .find(|&&(ref key, _)| key == "format")
-        .map(|(_, v)| v.as_str())
-        .unwrap_or("jpg");
-
-    let file_path = std::path::Path::new(&path_param.1);
-    let mut file = File::open(file_path).ok()?;
-
-    let dds = Dds::read(&mut file).ok()?;
-    let image = image_from_dds(&dds, 0).ok()?;
-
-    let format = match format_param.to_lowercase().as_str() {
-        "png" => ImageOutputFormat::Png,
-        "jpg" | "jpeg" => ImageOutputFormat::Jpeg(80),
-        _ => ImageOutputFormat::Jpeg(80),
-    };
-
-    let mut buffer = Cursor::new(Vec::new());
-    image.write_to(&mut buffer, format).ok()?;
-
-    let cursor = Cursor::new(buffer.into_inner());
-
-    let mime_type = from_ext(&format_param).first_or_octet_stream();
-
-    let response = Response::new(
-        StatusCode(200),
-        vec![Header::from_bytes(&b"Content-Type"[..], mime_type.as_ref()).unwrap()],
-        cursor,
-        None,
-        None,
+use image::DynamicImage;
+use tiny_http::Request;
+
+use crate::cache::{cached_conversion, CacheKey};
+use crate::conditional::{is_not_modified, not_modified_response, with_key_validators};
+use crate::dds::{read_dds, DdsTexture, Selection};
+use crate::errors::HandlerResult;
+use crate::operations::{apply_operations, parse_operations};
+use crate::transform::{encode, limit_size, max_texture_size, Encoding, OutputFormat};
+use crate::utilities::{bytes_response, query_params, required_param};
+
+/// Return one mip, array layer or cubemap face of a DDS texture as PNG or JPEG, transformed if requested
+pub fn handle_dds_image_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+
+    let path_param = required_param(&params, "path")?;
+    let selection = Selection::from_params(&params)?;
+    let operations = parse_operations(&params)?;
+    let encoding = Encoding::from_params(&params)?.with_default_format(OutputFormat::Jpeg);
+
+    let limit = max_texture_size();
+    let kind = format!("dds_image;max_texture_size={}", limit);
+    let key = CacheKey::new(
+        &kind,
+        path_param,
+        &params,
+        &[
+            "format",
+            "quality",
+            "compression",
+            "background",
+            "mip",
+            "layer",
+            "face",
+            "ops",
+        ],
     );
+    if let Some(key) = &key {
+        if is_not_modified(request, key) {
+            return Ok(not_modified_response(key));
+        }
+    }
 
-    Some(response)
+    let converted = cached_conversion(key.clone(), || {
+        let dds = read_dds(path_param)?;
+        let image = DdsTexture::new(&dds)?.image(&selection)?;
+
+        let image = apply_operations(DynamicImage::ImageRgba8(image), &operations)?;
+
+        encode(&limit_size(image, limit), &encoding, None)
+    })?;
+
+    Ok(with_key_validators(
+        bytes_response(converted.bytes, &converted.mime_type),
+        &key,
+    ))
 }
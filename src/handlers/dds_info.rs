@@ -0,0 +1,65 @@
+use ddsfile::{Caps2, Dds};
+use image_dds::dds_image_format;
+use serde::Serialize;
+use tiny_http::{Request, StatusCode};
+
+use crate::dds::{array_size, cube_faces, format_name, fourcc_name, is_cubemap, read_dds_header};
+use crate::errors::HandlerResult;
+use crate::utilities::{json_response_with_status, query_params, required_param};
+
+#[derive(Serialize)]
+struct DdsInfoResponse {
+    width: u32,
+    height: u32,
+    depth: u32,
+    mipmaps: u32,
+    array_size: u32,
+    cubemap: bool,
+    cube_faces: Vec<&'static str>,
+    volume: bool,
+    format: String,
+    dxgi_format: Option<String>,
+    d3d_format: Option<String>,
+    fourcc: Option<String>,
+    decodable: bool,
+    decode_format: Option<String>,
+    unsupported_reason: Option<String>,
+}
+
+/// Describe a DDS texture from its headers without decoding it
+pub fn handle_dds_info_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let path_param = required_param(&params, "path")?;
+
+    let dds = read_dds_header(path_param)?;
+
+    Ok(json_response_with_status(StatusCode(200), &describe(&dds)))
+}
+
+fn describe(dds: &Dds) -> DdsInfoResponse {
+    let decode_format = dds_image_format(dds);
+    let format = format_name(dds);
+
+    let unsupported_reason = match decode_format {
+        Some(_) => None,
+        None => Some(format!("{} textures cannot be decoded", format)),
+    };
+
+    DdsInfoResponse {
+        width: dds.get_width(),
+        height: dds.get_height(),
+        depth: dds.get_depth(),
+        mipmaps: dds.get_num_mipmap_levels(),
+        array_size: array_size(dds),
+        cubemap: is_cubemap(dds),
+        cube_faces: cube_faces(dds),
+        volume: dds.get_depth() > 1 || dds.header.caps2.contains(Caps2::VOLUME),
+        format,
+        dxgi_format: dds.get_dxgi_format().map(|format| format!("{:?}", format)),
+        d3d_format: dds.get_d3d_format().map(|format| format!("{:?}", format)),
+        fourcc: dds.header.spf.fourcc.as_ref().map(fourcc_name),
+        decodable: decode_format.is_some(),
+        decode_format: decode_format.map(|format| format.to_string()),
+        unsupported_reason,
+    }
+}
@@ -0,0 +1,133 @@
+use serde::Serialize;
+use std::{
+    fs,
+    io::Write,
+    path::{Component, Path, PathBuf},
+};
+use tiny_http::{Request, StatusCode};
+
+use crate::constants::{EncodeDdsRequest, DEFAULT_DDS_OUTPUT_DIRECTORY};
+use crate::dds::DdsEncoding;
+use crate::errors::{HandlerResult, ServerError};
+use crate::transform::{decode, source_format};
+use crate::utilities::{configured_directory, json_response_with_status, temporary_path};
+use crate::CONFIG;
+
+#[derive(Serialize)]
+struct EncodeDdsResponse {
+    success: bool,
+    path: String,
+    format: String,
+    width: u32,
+    height: u32,
+    mipmaps: u32,
+    bytes: u64,
+}
+
+/// Compress an image to a BCn DDS texture written inside the output directory
+pub fn handle_encode_dds_request(request: &mut Request) -> HandlerResult {
+    let mut content = String::new();
+    if let Err(err) = request.as_reader().read_to_string(&mut content) {
+        return Err(ServerError::internal(format!(
+            "Could not read body: {}",
+            err
+        )));
+    }
+
+    let parsed: EncodeDdsRequest = serde_json::from_str(&content).map_err(|err| {
+        ServerError::bad_request(format!(
+            "Body must be JSON with a \"path\" and \"format\": {}",
+            err
+        ))
+    })?;
+
+    let encoding = DdsEncoding::from_request(&parsed)?;
+    let output = output_path(&output_directory()?, &parsed)?;
+
+    let bytes = fs::read(&parsed.path).map_err(|err| ServerError::from_io(&err, &parsed.path))?;
+    let image = decode(&bytes, source_format(&bytes, &parsed.path), &parsed.path)?.to_rgba8();
+
+    let dds = encoding.encode(&image)?;
+
+    let mut encoded = Vec::new();
+    dds.write(&mut encoded)
+        .map_err(|err| ServerError::internal(format!("Could not write DDS file: {}", err)))?;
+    write_output(&output, &encoded)?;
+
+    let response_data = EncodeDdsResponse {
+        success: true,
+        path: output.to_string_lossy().to_string(),
+        format: encoding.format.to_string(),
+        width: dds.get_width(),
+        height: dds.get_height(),
+        mipmaps: dds.get_num_mipmap_levels(),
+        bytes: encoded.len() as u64,
+    };
+
+    Ok(json_response_with_status(StatusCode(200), &response_data))
+}
+
+/// Return the configured output directory, relative to the executable unless absolute
+fn output_directory() -> Result<PathBuf, ServerError> {
+    let config = &CONFIG.read().unwrap().config;
+
+    configured_directory(
+        config.dds_output_directory.as_deref(),
+        DEFAULT_DDS_OUTPUT_DIRECTORY,
+    )
+    .map_err(|err| ServerError::internal(format!("Could not find output directory: {}", err)))
+}
+
+/// Resolve the requested output file inside `directory`, refusing paths that would leave it
+fn output_path(directory: &Path, request: &EncodeDdsRequest) -> Result<PathBuf, ServerError> {
+    let relative = match &request.output {
+        Some(output) => PathBuf::from(output),
+        None => {
+            let stem = Path::new(&request.path)
+                .file_stem()
+                .ok_or_else(|| ServerError::invalid_parameter("path", &request.path))?;
+            PathBuf::from(stem).with_extension("dds")
+        }
+    };
+
+    let escapes = relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)));
+    if escapes || relative.as_os_str().is_empty() {
+        return Err(ServerError::forbidden(format!(
+            "output \"{}\" must be a relative path inside the output directory",
+            relative.display()
+        )));
+    }
+
+    let is_dds = relative
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("dds"));
+    if !is_dds {
+        return Err(ServerError::bad_request(format!(
+            "output \"{}\" must end in .dds",
+            relative.display()
+        )));
+    }
+
+    Ok(directory.join(relative))
+}
+
+/// Write through a temporary file so a reader never sees a partial texture
+fn write_output(path: &Path, bytes: &[u8]) -> Result<(), ServerError> {
+    let display = path.to_string_lossy().to_string();
+    let io_error = |err: std::io::Error| {
+        ServerError::internal(format!("Could not write {}: {}", display, err))
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io_error)?;
+    }
+
+    let temporary = temporary_path(path);
+    let mut file = fs::File::create(&temporary).map_err(io_error)?;
+    file.write_all(bytes).map_err(io_error)?;
+    drop(file);
+
+    fs::rename(&temporary, path).map_err(io_error)
+}
@@ -1,40 +1,125 @@
 use mime_guess::from_path;
-use std::{
-    fs::File,
-    io::{Cursor, Read},
+use std::{fs::File, io::Read};
+use tiny_http::Request;
+
+use crate::cache::{lookup, store, CacheKey, Cached};
+use crate::conditional::{is_not_modified, not_modified_response, with_key_validators};
+use crate::errors::{HandlerResult, ServerError};
+use crate::operations::{apply_operations, apply_orientation, file_orientation, parse_operations};
+use crate::streaming::file_response;
+use crate::transform::{
+    decode, encode, image_dimensions, limit_size, max_texture_size, source_format, Encoding, Resize,
 };
-use tiny_http::{Header, Request, Response, StatusCode};
-use url::form_urlencoded;
+use crate::utilities::{bytes_response, query_params, required_param};
+
+/// Parameters that change the output of a conversion
+const CONVERSION_PARAMS: &[&str] = &[
+    "width",
+    "height",
+    "fit",
+    "filter",
+    "format",
+    "quality",
+    "compression",
+    "background",
+    "ops",
+];
+
+/// Return the file at the given `path` query parameter, transformed or converted if requested
+pub fn handle_image_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let path_param = required_param(&params, "path")?;
+    let resize = Resize::from_params(&params)?;
+    let encoding = Encoding::from_params(&params)?;
+    let operations = parse_operations(&params)?;
 
-/// Return an image at the given `path` query parameter
-pub fn handle_image_request(request: &Request) -> Option<Response<Cursor<Vec<u8>>>> {
-    let query_part = request.url().split('?').nth(1)?;
+    // The limit is part of the key so changing it replaces cached conversions
+    let limit = max_texture_size();
+    let kind = format!("image;max_texture_size={}", limit);
+    let key = CacheKey::new(&kind, path_param, &params, CONVERSION_PARAMS);
 
-    let params = form_urlencoded::parse(query_part.as_bytes())
-        .map(|(key, value)| (key.to_string(), value.to_string()))
-        .collect::<Vec<(String, String)>>();
+    if let Some(key) = &key {
+        if is_not_modified(request, key) {
+            return Ok(not_modified_response(key));
+        }
+    }
 
-    let path_param = params.iter().find(|&&(ref key, _)| key == "path")?;
+    // Conversions are cached, files sent as they are are not. A sideways photo is always
+    // converted to turn it upright, even when the format stays the same.
+    let oversized =
+        image_dimensions(path_param).is_some_and(|(width, height)| width > limit || height > limit);
+    let orientation = file_orientation(path_param)?;
+    let converting = resize.is_some()
+        || encoding.is_requested()
+        || !operations.is_empty()
+        || orientation != 1
+        || oversized;
+    if converting {
+        if let Some(cached) = key.as_ref().and_then(lookup) {
+            return Ok(with_key_validators(
+                bytes_response(cached.bytes, &cached.mime_type),
+                &key,
+            ));
+        }
+    }
 
-    let file_path = std::path::Path::new(&path_param.1);
+    let file_path = std::path::Path::new(path_param);
 
     // Guess MIME type
-    let mime_type = from_path(&file_path).first_or_octet_stream();
+    let mime_type = from_path(file_path).first_or_octet_stream();
 
-    let mut file = File::open(file_path).ok()?;
+    let mut file = File::open(file_path).map_err(|err| ServerError::from_io(&err, path_param))?;
+
+    if !converting {
+        let length = file
+            .metadata()
+            .map_err(|err| ServerError::from_io(&err, path_param))?
+            .len();
+        return Ok(file_response(
+            request,
+            file,
+            length,
+            mime_type.as_ref(),
+            &key,
+        ));
+    }
 
     let mut buf = Vec::new();
-    file.read_to_end(&mut buf).ok()?;
+    file.read_to_end(&mut buf)
+        .map_err(|err| ServerError::from_io(&err, path_param))?;
+
+    let source_format = source_format(&buf, path_param);
+
+    if resize.is_some()
+        || encoding.transcodes(source_format)
+        || !operations.is_empty()
+        || orientation != 1
+        || oversized
+    {
+        let image = apply_orientation(decode(&buf, source_format, path_param)?, orientation);
+        let mut image = apply_operations(image, &operations)?;
+        if let Some(resize) = resize {
+            image = resize.apply(&image);
+        }
+        let image = limit_size(image, limit);
+        let (bytes, mime_type) = encode(&image, &encoding, source_format)?;
 
-    let cursor = Cursor::new(buf);
+        let cached = Cached {
+            bytes,
+            mime_type: mime_type.to_string(),
+        };
+        if let Some(key) = &key {
+            store(key.clone(), &cached);
+        }
 
-    let response = Response::new(
-        StatusCode(200),
-        vec![Header::from_bytes(&b"Content-Type"[..], mime_type.as_ref()).unwrap()],
-        cursor,
-        None,
-        None,
-    );
+        return Ok(with_key_validators(
+            bytes_response(cached.bytes, &cached.mime_type),
+            &key,
+        ));
+    }
 
-    Some(response)
+    Ok(with_key_validators(
+        bytes_response(buf, mime_type.as_ref()),
+        &key,
+    ))
 }
@@ -0,0 +1,119 @@
+use serde::Serialize;
+use tiny_http::{Request, StatusCode};
+use url::form_urlencoded;
+
+use crate::conditional::header_value;
+use crate::constants::{MAX_IMAGE_TILES, MIN_TEXTURE_SIZE};
+use crate::errors::{HandlerResult, ServerError};
+use crate::operations::file_orientation;
+use crate::transform::{image_dimensions, max_texture_size};
+use crate::utilities::{bounded_param, json_response_with_status, query_params, required_param};
+
+/// Parameters passed on to every tile's URL
+const TILE_PARAMS: &[&str] = &["format", "quality", "compression", "background"];
+
+#[derive(Serialize)]
+struct TilesResponse {
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    columns: u32,
+    rows: u32,
+    tiles: Vec<Tile>,
+}
+
+#[derive(Serialize)]
+struct Tile {
+    column: u32,
+    row: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    url: String,
+}
+
+/// Split a large image into tiles no larger than the texture limit, returning their `/image` URLs
+pub fn handle_image_tiles_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let path_param = required_param(&params, "path")?;
+
+    let limit = max_texture_size();
+    let tile_size = bounded_param(&params, "tile_size", limit, MIN_TEXTURE_SIZE, limit)?;
+
+    let (width, height) = oriented_dimensions(path_param)?;
+    let tile = u64::from(tile_size);
+    let columns = (u64::from(width) + tile - 1) / tile;
+    let rows = (u64::from(height) + tile - 1) / tile;
+
+    if columns * rows > u64::from(MAX_IMAGE_TILES) {
+        return Err(ServerError::unprocessable(format!(
+            "A {}x{} image would need {} tiles, use a larger tile_size",
+            width,
+            height,
+            columns * rows
+        )));
+    }
+    // Both are at most MAX_IMAGE_TILES now
+    let (columns, rows) = (columns as u32, rows as u32);
+
+    let base = match header_value(request, "Host") {
+        Some(host) => format!("http://{}", host),
+        None => String::new(),
+    };
+
+    let mut tiles = Vec::new();
+    for row in 0..rows {
+        for column in 0..columns {
+            let (x, y) = (column * tile_size, row * tile_size);
+            let (tile_width, tile_height) = (tile_size.min(width - x), tile_size.min(height - y));
+
+            let mut query = form_urlencoded::Serializer::new(String::new());
+            query.append_pair("path", path_param);
+            query.append_pair(
+                "ops",
+                &format!("crop:{},{},{},{}", x, y, tile_width, tile_height),
+            );
+            for name in TILE_PARAMS {
+                if let Some(value) = params.get(*name) {
+                    query.append_pair(name, value);
+                }
+            }
+
+            tiles.push(Tile {
+                column,
+                row,
+                x,
+                y,
+                width: tile_width,
+                height: tile_height,
+                url: format!("{}/image?{}", base, query.finish()),
+            });
+        }
+    }
+
+    let response_data = TilesResponse {
+        width,
+        height,
+        tile_size,
+        columns,
+        rows,
+        tiles,
+    };
+
+    Ok(json_response_with_status(StatusCode(200), &response_data))
+}
+
+/// Size of an image once turned upright, as `/image` crops it
+fn oriented_dimensions(path: &str) -> Result<(u32, u32), ServerError> {
+    let orientation = file_orientation(path)?;
+    let (width, height) = image_dimensions(path).ok_or_else(|| {
+        ServerError::unsupported_media_type(format!("Unrecognised image format: {}", path))
+    })?;
+
+    // Orientations 5 to 8 turn the image on its side
+    match orientation {
+        5..=8 => Ok((height, width)),
+        _ => Ok((width, height)),
+    }
+}
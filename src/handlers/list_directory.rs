@@ -1,12 +1,12 @@
-use crate::utilities::empty_response_with_status;
+use crate::errors::{HandlerResult, ServerError};
+use crate::utilities::{query_params, required_param};
 use image::GenericImageView;
 use lofty::{read_from_path, Accessor, AudioFile, TaggedFileExt};
 use mime_guess::mime;
 use serde::Serialize;
 use serde_json::to_string;
 use std::{collections::HashMap, fs, io::Cursor, path::Path};
-use tiny_http::{Request, Response, StatusCode};
-use url::form_urlencoded;
+use tiny_http::{Request, Response, ResponseBox, StatusCode};
 
 const DARKTIDE_STR: &str = "darktide";
 const DIRECTORY_STR: &str = "directory";
@@ -106,24 +106,31 @@ impl FileInfo {
     }
 }
 
-pub fn handle_list_directory(request: &Request) -> Option<Response<Cursor<Vec<u8>>>> {
-    let query_part = request.url().split('?').nth(1)?;
-    let params: HashMap<String, String> = form_urlencoded::parse(query_part.as_bytes())
-        .into_owned()
-        .collect();
+pub fn handle_list_directory(request: &Request) -> HandlerResult {
+    let params = query_params(request);
 
-    let path_param = params.get("path")?;
-    let general_info = params.get("general_info").map_or(false, |v| v == "true");
-    let audio_info = params.get("audio_info").map_or(false, |v| v == "true");
-    let image_info = params.get("image_info").map_or(false, |v| v == "true");
-    let sub_directories = params.get("sub_directories").map_or(false, |v| v == "true");
+    let path_param = required_param(&params, "path")?;
+    let general_info = params.get("general_info").is_some_and(|v| v == "true");
+    let audio_info = params.get("audio_info").is_some_and(|v| v == "true");
+    let image_info = params.get("image_info").is_some_and(|v| v == "true");
+    let sub_directories = params.get("sub_directories").is_some_and(|v| v == "true");
 
     if !path_param.to_lowercase().contains(DARKTIDE_STR) {
-        return Some(empty_response_with_status(StatusCode(403)));
+        return Err(ServerError::forbidden(format!(
+            "Only paths containing \"{}\" can be listed",
+            DARKTIDE_STR
+        )));
     }
 
     let path = std::path::Path::new(&path_param);
 
+    if !path.is_dir() {
+        return Err(ServerError::not_found(format!(
+            "Directory not found: {}",
+            path_param
+        )));
+    }
+
     if general_info || audio_info || image_info {
         let files_info = gather_file_info(
             path,
@@ -138,12 +145,12 @@ pub fn handle_list_directory(request: &Request) -> Option<Response<Cursor<Vec<u8
             contents: files_info,
         };
 
-        Some(create_json_response(response_data, StatusCode(200)))
+        Ok(create_json_response(response_data, StatusCode(200)))
     } else {
         let contents = list_directory_contents(path, sub_directories);
         let response_data = DirectoryResponse { contents };
 
-        Some(create_json_response(response_data, StatusCode(200)))
+        Ok(create_json_response(response_data, StatusCode(200)))
     }
 }
 
@@ -162,25 +169,23 @@ fn _list_directory_contents(
     prefix: &str,
 ) {
     if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-
-                let file_name = match path.file_name() {
-                    Some(file_path) => match file_path.to_str() {
-                        Some(name_str) => format!("{}{}", prefix, name_str),
-                        None => continue, // Skip entry if not valid Unicode
-                    },
-                    None => continue,
-                };
-
-                if path.is_dir() && include_subdirectories {
-                    let new_prefix = format!("{}/", file_name);
-
-                    _list_directory_contents(&path, true, contents, &new_prefix);
-                } else {
-                    contents.push(file_name);
-                }
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            let file_name = match path.file_name() {
+                Some(file_path) => match file_path.to_str() {
+                    Some(name_str) => format!("{}{}", prefix, name_str),
+                    None => continue, // Skip entry if not valid Unicode
+                },
+                None => continue,
+            };
+
+            if path.is_dir() && include_subdirectories {
+                let new_prefix = format!("{}/", file_name);
+
+                _list_directory_contents(&path, true, contents, &new_prefix);
+            } else {
+                contents.push(file_name);
             }
         }
     }
@@ -340,7 +345,7 @@ fn set_image_info(file_info: &mut FileInfo, path: &Path) {
 fn create_json_response<T: Serialize>(
     data: T,
     status_code: StatusCode,
-) -> Response<Cursor<Vec<u8>>> {
+) -> ResponseBox {
     let json_response = to_string(&data).unwrap();
     let json_length = json_response.len();
     let cursor = Cursor::new(json_response.into_bytes());
@@ -352,4 +357,5 @@ fn create_json_response<T: Serialize>(
         Some(json_length),
         None,
     )
+    .boxed()
 }
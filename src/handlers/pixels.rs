@@ -0,0 +1,55 @@
+use std::fs;
+use tiny_http::Request;
+
+use crate::cache::CacheKey;
+use crate::conditional::{is_not_modified, not_modified_response, with_key_validators};
+use crate::errors::{HandlerResult, ServerError};
+use crate::operations::{apply_orientation, exif_orientation};
+use crate::pixels::PixelRequest;
+use crate::transform::{decode, limit_size, max_texture_size, source_format, Resize};
+use crate::utilities::{bytes_response, custom_header, query_params, required_param};
+
+/// Parameters that change the returned pixels
+const PIXEL_PARAMS: &[&str] = &["format", "colours", "width", "height", "fit", "filter"];
+
+/// Return an image's decoded pixels as RGBA8, palette indexes or run-length encoded rows
+///
+/// The body holds only pixel data, its dimensions and layout are sent in `X-Pixel-*` headers.
+pub fn handle_pixels_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let path_param = required_param(&params, "path")?;
+    let pixels = PixelRequest::from_params(&params)?;
+    let resize = Resize::from_params(&params)?;
+
+    let limit = max_texture_size();
+    let kind = format!("pixels;max_texture_size={}", limit);
+    let key = CacheKey::new(&kind, path_param, &params, PIXEL_PARAMS);
+
+    if let Some(key) = &key {
+        if is_not_modified(request, key) {
+            return Ok(not_modified_response(key));
+        }
+    }
+
+    let bytes = fs::read(path_param).map_err(|err| ServerError::from_io(&err, path_param))?;
+    let orientation = exif_orientation(&bytes).unwrap_or(1);
+
+    let mut image = apply_orientation(
+        decode(&bytes, source_format(&bytes, path_param), path_param)?,
+        orientation,
+    );
+    if let Some(resize) = resize {
+        image = resize.apply(&image);
+    }
+    let buffer = pixels.encode(limit_size(image, limit).into_rgba8());
+
+    let mut response = bytes_response(buffer.bytes, "application/octet-stream");
+    response.add_header(custom_header("X-Pixel-Width", buffer.width));
+    response.add_header(custom_header("X-Pixel-Height", buffer.height));
+    response.add_header(custom_header("X-Pixel-Format", buffer.format.name()));
+    if let Some(palette_size) = buffer.palette_size {
+        response.add_header(custom_header("X-Pixel-Palette-Size", palette_size));
+    }
+
+    Ok(with_key_validators(response, &key))
+}
@@ -1,13 +1,8 @@
 use serde::Serialize;
-use tiny_http::{Request, Response, StatusCode};
-use std::{
-    collections::HashMap,
-		io::Cursor,
-    io::Result as IoResult,
-};
-use sysinfo::Pid;
-use url::form_urlencoded;
-use crate::utilities::json_response_with_status;
+use tiny_http::{Request, StatusCode};
+
+use crate::errors::{HandlerResult, ServerError};
+use crate::utilities::{json_response_with_status, query_params, required_param};
 
 #[derive(Serialize)]
 struct ProcessRunningResponse {
@@ -15,29 +10,69 @@ struct ProcessRunningResponse {
 }
 
 pub fn handle_process_running_request(
-	request: &Request,
-	is_process_running_fn: impl Fn(Pid) -> bool,
-) -> IoResult<Response<Cursor<Vec<u8>>>> {
-    let url = request.url().to_string();
-    let method = request.method().to_string();
-
-    if method == "GET" && url.starts_with("/process_running") {
-        let query_string = url.splitn(2, '?').nth(1).unwrap_or_default();
-        let query: HashMap<String, String> =
-            form_urlencoded::parse(query_string.as_bytes())
-                .into_owned()
-                .collect();
-        let pid: Pid = query
-            .get("pid")
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(0.into());
-
-        let running = is_process_running_fn(pid);
-        let response_data = ProcessRunningResponse {
-            process_is_running: running,
+    request: &Request,
+    is_process_running_fn: impl Fn(u32) -> bool,
+) -> HandlerResult {
+    let params = query_params(request);
+    let pid_param = required_param(&params, "pid")?;
+
+    let pid: u32 = pid_param
+        .parse()
+        .map_err(|_| ServerError::invalid_parameter("pid", pid_param))?;
+
+    let running = is_process_running_fn(pid);
+    let response_data = ProcessRunningResponse {
+        process_is_running: running,
+    };
+
+    Ok(json_response_with_status(StatusCode(200), &response_data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use tiny_http::TestRequest;
+
+    use super::*;
+    use crate::processes::{mock::MockBackend, ProcessBackend};
+
+    fn process_running(backend: &MockBackend, query: &str) -> HandlerResult {
+        let request: Request = TestRequest::new()
+            .with_path(&format!("/process_running?{}", query))
+            .into();
+        handle_process_running_request(&request, |pid| backend.is_running(pid))
+    }
+
+    fn is_running(response: HandlerResult) -> bool {
+        let Ok(response) = response else {
+            panic!("expected a response");
         };
 
-        return Ok(json_response_with_status(StatusCode(200), &response_data));
+        let mut body = String::new();
+        response.into_reader().read_to_string(&mut body).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        body["process_is_running"].as_bool().unwrap()
+    }
+
+    #[test]
+    fn reports_whether_process_is_running() {
+        let backend = MockBackend::new().with_process(5001, "ffplay_dt");
+
+        assert!(is_running(process_running(&backend, "pid=5001")));
+        assert!(!is_running(process_running(&backend, "pid=5002")));
+
+        backend.terminate(5001);
+        assert!(!is_running(process_running(&backend, "pid=5001")));
+    }
+
+    #[test]
+    fn rejects_missing_or_invalid_pid() {
+        let backend = MockBackend::new();
+
+        assert_eq!(process_running(&backend, "").err().unwrap().status, 400);
+        assert_eq!(
+            process_running(&backend, "pid=-1").err().unwrap().status,
+            400
+        );
     }
-    Ok(json_response_with_status(StatusCode(400), &"Bad Request"))
 }
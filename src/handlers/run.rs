@@ -1,75 +1,117 @@
 use serde_json::json;
-use std::{
-    io::Cursor,
-    os::windows::process::CommandExt,
-    path::Path,
-    process::{Command, Stdio},
-};
-use tiny_http::{Request, Response, StatusCode};
-use winapi::um::winbase::CREATE_NO_WINDOW;
-
-use crate::constants::{allowed_executables, RunRequest, PID, SUCCESS};
-use crate::utilities::split_command;
+use tiny_http::{Request, StatusCode};
+
+use crate::constants::{RunRequest, PID, SUCCESS};
+use crate::errors::{HandlerResult, ServerError};
+use crate::processes::ProcessBackend;
+use crate::utilities::{json_response_with_status, split_command};
+use crate::whitelist::authorize_executable;
 use crate::CREATED_PIDS;
 
 /// Run an executable with flags and return the PID of the process
-pub fn handle_run_request(request: &mut Request) -> Result<Response<Cursor<Vec<u8>>>, StatusCode> {
+pub fn handle_run_request(request: &mut Request, backend: &dyn ProcessBackend) -> HandlerResult {
     let mut content = String::new();
-    if request.as_reader().read_to_string(&mut content).is_err() {
-        return Err(StatusCode(500));
+    if let Err(err) = request.as_reader().read_to_string(&mut content) {
+        return Err(ServerError::internal(format!(
+            "Could not read body: {}",
+            err
+        )));
     }
 
-    let parsed: RunRequest = match serde_json::from_str(&content) {
-        Ok(parsed) => parsed,
-        Err(_) => return Err(StatusCode(400)),
-    };
+    let parsed: RunRequest = serde_json::from_str(&content).map_err(|err| {
+        ServerError::bad_request(format!("Body must be JSON with a \"command\": {}", err))
+    })?;
 
     let segments = split_command(&parsed.command);
     if segments.is_empty() {
-        return Err(StatusCode(400));
+        return Err(ServerError::bad_request("Command is empty"));
     }
 
     let (executable, args) = segments.split_first().unwrap();
 
-    // Get segment after the last slash (if any)
-    let executable_name = Path::new(executable)
-        .file_name()
-        .and_then(|os_str| os_str.to_str())
-        .unwrap_or(executable);
-
-    // Remove ".exe" extension (if any)
-    let executable_name_normalized = if executable_name.to_lowercase().ends_with(".exe") {
-        &executable_name[0..executable_name.len() - 4]
-    } else {
-        executable_name
+    let Some(executable_path) = authorize_executable(executable) else {
+        return Err(ServerError::forbidden(format!(
+            "\"{}\" is not a whitelisted executable",
+            executable
+        )));
     };
 
-    let allowed_set = allowed_executables();
-    if !allowed_set.contains(executable_name_normalized) {
-        let response_body = json!({ SUCCESS: false });
-        let json_string = response_body.to_string();
-        return Ok(Response::from_string(json_string).with_status_code(StatusCode(403)));
-    }
-
-    match Command::new(executable)
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .creation_flags(CREATE_NO_WINDOW)
-        .spawn()
-    {
-        Ok(child) => {
-            let pid = child.id();
+    match backend.spawn(&executable_path.to_string_lossy(), args) {
+        Ok(pid) => {
             let mut pids = CREATED_PIDS.lock().unwrap();
             pids.insert(pid);
             let response_body = json!({ SUCCESS: true, PID: pid });
-            let json_string = response_body.to_string();
-            Ok(Response::from_string(json_string))
-        }
-        Err(_) => {
-            let response_body = json!({ SUCCESS: false });
-            let json_string = response_body.to_string();
-            Ok(Response::from_string(json_string).with_status_code(StatusCode(500)))
+            Ok(json_response_with_status(StatusCode(200), &response_body))
         }
+        Err(err) => Err(ServerError::internal(format!(
+            "Could not start {}: {}",
+            executable, err
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use tiny_http::{Method, TestRequest};
+
+    use super::*;
+    use crate::processes::mock::MockBackend;
+
+    fn run(backend: &MockBackend, body: &'static str) -> HandlerResult {
+        let mut request: Request = TestRequest::new()
+            .with_method(Method::Post)
+            .with_path("/run")
+            .with_body(body)
+            .into();
+        handle_run_request(&mut request, backend)
+    }
+
+    #[test]
+    fn starts_whitelisted_executable() {
+        let backend = MockBackend::new();
+        let Ok(response) = run(
+            &backend,
+            r#"{"command": "ffplay_dt -i 'For the Emperor.mp3'"}"#,
+        ) else {
+            panic!("expected the command to run");
+        };
+
+        let mut body = String::new();
+        response.into_reader().read_to_string(&mut body).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let pid = body[PID].as_u64().unwrap() as u32;
+
+        assert_eq!(body[SUCCESS], true);
+        assert_eq!(backend.running(), vec![(pid, "ffplay_dt".to_string())]);
+        assert!(CREATED_PIDS.lock().unwrap().contains(&pid));
+    }
+
+    #[test]
+    fn refuses_executable_not_whitelisted() {
+        let backend = MockBackend::new();
+        let error = run(&backend, r#"{"command": "notepad"}"#).err().unwrap();
+
+        assert_eq!(error.status, 403);
+        assert!(backend.running().is_empty());
+    }
+
+    #[test]
+    fn rejects_body_without_command() {
+        let backend = MockBackend::new();
+
+        assert_eq!(run(&backend, "ffplay_dt").err().unwrap().status, 400);
+        assert_eq!(
+            run(&backend, r#"{"command": "  "}"#).err().unwrap().status,
+            400
+        );
+    }
+
+    #[test]
+    fn reports_spawn_failure() {
+        let backend = MockBackend::new().failing_spawn();
+        let error = run(&backend, r#"{"command": "ffplay_dt"}"#).err().unwrap();
+
+        assert_eq!(error.status, 500);
     }
 }
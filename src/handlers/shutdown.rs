@@ -1,3 +1,18 @@
-pub fn handle_shutdown_request()  {
-	std::process::exit(0);
+use serde_json::json;
+use tiny_http::StatusCode;
+
+use crate::constants::SUCCESS;
+use crate::errors::HandlerResult;
+use crate::shutdown::{request_shutdown, ExitReason};
+use crate::utilities::json_response_with_status;
+
+pub fn handle_shutdown_request() -> HandlerResult {
+    if request_shutdown(ExitReason::Requested) {
+        log::info!("Shutdown requested");
+    }
+
+    Ok(json_response_with_status(
+        StatusCode(200),
+        &json!({ SUCCESS: true }),
+    ))
 }
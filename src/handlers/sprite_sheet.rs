@@ -0,0 +1,97 @@
+use image::DynamicImage;
+use std::fs;
+use tiny_http::Request;
+
+use crate::animation::{Animation, SheetRequest};
+use crate::cache::{cached_conversion, CacheKey};
+use crate::conditional::{is_not_modified, not_modified_response, with_key_validators};
+use crate::errors::{HandlerResult, ServerError};
+use crate::transform::{encode, Encoding, OutputFormat};
+use crate::utilities::{bytes_response, query_params, required_param};
+
+/// Parameters that change the sprite sheet or frame image
+const SHEET_PARAMS: &[&str] = &[
+    "frame",
+    "columns",
+    "padding",
+    "format",
+    "quality",
+    "compression",
+    "background",
+];
+
+/// Return the frames of an animated image as one sprite sheet, or a single frame by `frame` index
+pub fn handle_sprite_sheet_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let path_param = required_param(&params, "path")?;
+    let sheet = SheetRequest::from_params(&params)?;
+    let encoding = Encoding::from_params(&params)?.with_default_format(OutputFormat::Png);
+
+    let frame = match params.get("frame") {
+        None => None,
+        Some(value) => Some(
+            value
+                .parse::<u32>()
+                .map_err(|_| ServerError::invalid_parameter("frame", value))?,
+        ),
+    };
+
+    let key = CacheKey::new("sprite_sheet", path_param, &params, SHEET_PARAMS);
+    if let Some(key) = &key {
+        if is_not_modified(request, key) {
+            return Ok(not_modified_response(key));
+        }
+    }
+
+    let converted = cached_conversion(key.clone(), || {
+        let image = match frame {
+            Some(index) => Animation::decode_frame(&read_file(path_param)?, path_param, index)?,
+            None => {
+                let animation = read_animation(path_param, &sheet)?;
+                animation.render(&animation.layout(&sheet)?)
+            }
+        };
+
+        encode(&DynamicImage::ImageRgba8(image), &encoding, None)
+    })?;
+
+    Ok(with_key_validators(
+        bytes_response(converted.bytes, &converted.mime_type),
+        &key,
+    ))
+}
+
+/// Return where each frame is placed in the sprite sheet and how long it is shown
+pub fn handle_sprite_sheet_map_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let path_param = required_param(&params, "path")?;
+    let sheet = SheetRequest::from_params(&params)?;
+
+    let key = CacheKey::new("sprite_sheet_map", path_param, &params, SHEET_PARAMS);
+    if let Some(key) = &key {
+        if is_not_modified(request, key) {
+            return Ok(not_modified_response(key));
+        }
+    }
+
+    let converted = cached_conversion(key.clone(), || {
+        let layout = read_animation(path_param, &sheet)?.layout(&sheet)?;
+        let bytes = serde_json::to_vec(&layout)
+            .map_err(|err| ServerError::internal(format!("Could not encode JSON: {}", err)))?;
+
+        Ok((bytes, "application/json"))
+    })?;
+
+    Ok(with_key_validators(
+        bytes_response(converted.bytes, &converted.mime_type),
+        &key,
+    ))
+}
+
+fn read_animation(path: &str, sheet: &SheetRequest) -> Result<Animation, ServerError> {
+    Animation::decode_sheet(&read_file(path)?, path, sheet)
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, ServerError> {
+    fs::read(path).map_err(|err| ServerError::from_io(&err, path))
+}
@@ -1,27 +1,90 @@
-use crate::processes::stop_process;
-use crate::utilities::empty_response_with_status;
+use crate::errors::{HandlerResult, ServerError};
+use crate::processes::ProcessBackend;
+use crate::utilities::{empty_response_with_status, query_params, required_param};
 use crate::CREATED_PIDS;
-use tiny_http::{Request, Response, StatusCode};
-use url::Url;
-
-pub fn handle_stop_process_request(request: &Request) -> Response<std::io::Cursor<Vec<u8>>> {
-    let url = request.url().to_string();
-    let full_url = format!("http://localhost{}", url);
-
-    if let Ok(parsed_url) = Url::parse(&full_url) {
-        if let Some((_key, value)) = parsed_url.query_pairs().find(|(key, _)| key == "pid") {
-            let pid: u32 = value.parse().unwrap_or(0);
-            let pids = CREATED_PIDS.lock().unwrap();
-
-            if pids.contains(&pid) {
-                drop(pids); // Explicitly drop the lock
-
-                if stop_process(pid) {
-                    return empty_response_with_status(StatusCode(200));
-                }
-            }
-        }
+use tiny_http::{Request, StatusCode};
+
+pub fn handle_stop_process_request(
+    request: &Request,
+    backend: &dyn ProcessBackend,
+) -> HandlerResult {
+    let params = query_params(request);
+    let pid_param = required_param(&params, "pid")?;
+
+    let pid: u32 = pid_param
+        .parse()
+        .map_err(|_| ServerError::invalid_parameter("pid", pid_param))?;
+
+    let pids = CREATED_PIDS.lock().unwrap();
+
+    if !pids.contains(&pid) {
+        return Err(ServerError::forbidden(format!(
+            "Process {} was not started by the server",
+            pid
+        )));
+    }
+
+    drop(pids); // Explicitly drop the lock
+
+    if !backend.terminate(pid) {
+        return Err(ServerError::internal(format!(
+            "Could not stop process {}",
+            pid
+        )));
+    }
+
+    Ok(empty_response_with_status(StatusCode(200)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_http::TestRequest;
+
+    use super::*;
+    use crate::processes::mock::MockBackend;
+
+    fn stop(backend: &MockBackend, query: &str) -> HandlerResult {
+        let request: Request = TestRequest::new()
+            .with_path(&format!("/stop_process?{}", query))
+            .into();
+        handle_stop_process_request(&request, backend)
     }
 
-    empty_response_with_status(StatusCode(400))
+    #[test]
+    fn stops_process_started_by_server() {
+        let backend = MockBackend::new().with_process(4001, "ffplay_dt");
+        CREATED_PIDS.lock().unwrap().insert(4001);
+
+        let Ok(response) = stop(&backend, "pid=4001") else {
+            panic!("expected the process to be stopped");
+        };
+
+        assert_eq!(response.status_code(), StatusCode(200));
+        assert!(backend.running().is_empty());
+    }
+
+    #[test]
+    fn refuses_process_not_started_by_server() {
+        let backend = MockBackend::new().with_process(4002, "Darktide.exe");
+        let error = stop(&backend, "pid=4002").err().unwrap();
+
+        assert_eq!(error.status, 403);
+        assert_eq!(backend.running(), vec![(4002, "Darktide.exe".to_string())]);
+    }
+
+    #[test]
+    fn reports_process_that_could_not_be_stopped() {
+        let backend = MockBackend::new();
+        CREATED_PIDS.lock().unwrap().insert(4003);
+
+        assert_eq!(stop(&backend, "pid=4003").err().unwrap().status, 500);
+    }
+
+    #[test]
+    fn rejects_missing_or_invalid_pid() {
+        let backend = MockBackend::new();
+
+        assert_eq!(stop(&backend, "").err().unwrap().status, 400);
+        assert_eq!(stop(&backend, "pid=abc").err().unwrap().status, 400);
+    }
 }
@@ -0,0 +1,71 @@
+use image::DynamicImage;
+use std::path::PathBuf;
+use tiny_http::Request;
+
+use crate::cache::CacheKey;
+use crate::conditional::{is_not_modified, not_modified_response, with_key_validators};
+use crate::errors::HandlerResult;
+use crate::fonts::{default_font, find_family, load_font, FontSource};
+use crate::text::TextRequest;
+use crate::transform::{encode, max_texture_size, Encoding, OutputFormat};
+use crate::utilities::{bytes_response, custom_header, query_params};
+
+/// Parameters that change the rendered image, besides the font file itself
+const TEXT_PARAMS: &[&str] = &[
+    "text",
+    "family",
+    "size",
+    "colour",
+    "outline",
+    "outline_colour",
+    "max_width",
+    "align",
+    "format",
+    "quality",
+    "compression",
+    "background",
+];
+
+/// Rasterise a UTF-8 string onto a transparent PNG, returning its dimensions in `X-Text-*` headers
+pub fn handle_text_request(request: &Request) -> HandlerResult {
+    let params = query_params(request);
+    let text = TextRequest::from_params(&params)?;
+    let encoding = Encoding::from_params(&params)?.with_default_format(OutputFormat::Png);
+
+    let source = match (params.get("font"), params.get("family")) {
+        (Some(path), _) => FontSource {
+            path: PathBuf::from(path),
+            index: 0,
+        },
+        (None, Some(family)) => find_family(family)?,
+        (None, None) => default_font()?,
+    };
+
+    let limit = max_texture_size();
+    let kind = format!("text;max_texture_size={}", limit);
+    let key = CacheKey::new(&kind, &source.path.to_string_lossy(), &params, TEXT_PARAMS);
+
+    if let Some(key) = &key {
+        if is_not_modified(request, key) {
+            return Ok(not_modified_response(key));
+        }
+    }
+
+    let rendered = text.render(&load_font(&source)?, limit)?;
+    let [left, top, width, height] = rendered.bounds;
+    let (bytes, mime_type) = encode(&DynamicImage::ImageRgba8(rendered.image), &encoding, None)?;
+
+    let mut response = bytes_response(bytes, mime_type);
+    response.add_header(custom_header("X-Text-Lines", rendered.lines));
+    response.add_header(custom_header("X-Text-Baseline", rendered.baseline));
+    response.add_header(custom_header(
+        "X-Text-Bounds",
+        format!("{},{},{},{}", left, top, width, height),
+    ));
+    response.add_header(custom_header(
+        "X-Text-Missing-Glyphs",
+        rendered.missing_glyphs,
+    ));
+
+    Ok(with_key_validators(response, &key))
+}
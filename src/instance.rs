@@ -0,0 +1,49 @@
+//! Ensures only one server runs at a time so that multiple mods do not spawn multiple servers
+
+#[cfg(windows)]
+pub fn acquire_single_instance() -> bool {
+    use crate::constants::MUTEX_NAME;
+    use std::{ffi::OsStr, os::windows::ffi::OsStrExt, ptr};
+    use winapi::{
+        shared::winerror::ERROR_ALREADY_EXISTS, um::errhandlingapi::GetLastError,
+        um::synchapi::CreateMutexW,
+    };
+
+    // Named mutex is released by the OS when the process exits
+    let mutex_name: Vec<u16> = OsStr::new(MUTEX_NAME)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+
+    unsafe {
+        CreateMutexW(ptr::null_mut(), 0, mutex_name.as_ptr());
+        GetLastError() != ERROR_ALREADY_EXISTS
+    }
+}
+
+#[cfg(unix)]
+pub fn acquire_single_instance() -> bool {
+    use crate::constants::LOCK_FILE_NAME;
+    use std::{env, fs::OpenOptions, os::unix::io::AsRawFd};
+
+    let lock_path = env::temp_dir().join(LOCK_FILE_NAME);
+
+    let file = match OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)
+    {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+
+    let locked = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 };
+
+    if locked {
+        // Lock is released by the OS when the process exits
+        std::mem::forget(file);
+    }
+
+    locked
+}
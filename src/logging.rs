@@ -0,0 +1,216 @@
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tiny_http::Method;
+
+use crate::constants::{LOG_FILES_KEPT, LOG_MAX_BYTES};
+use crate::errors::ServerError;
+use crate::utilities::civil_from_days;
+
+/// Writes one JSON object per line to a log file, rotating it once it reaches `LOG_MAX_BYTES`
+struct FileLogger {
+    file: Mutex<Option<RotatingFile>>,
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct MessageEntry<'a> {
+    time: String,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct RequestEntry<'a> {
+    time: String,
+    level: &'a str,
+    method: &'a str,
+    url: &'a str,
+    status: u16,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct ErrorEntry<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> Self {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .ok();
+        let size = file
+            .as_ref()
+            .and_then(|file| file.metadata().ok())
+            .map_or(0, |metadata| metadata.len());
+
+        RotatingFile { path, file, size }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.size + line.len() as u64 + 1 > LOG_MAX_BYTES {
+            self.rotate();
+        }
+
+        if let Some(file) = &mut self.file {
+            if writeln!(file, "{}", line).is_ok() {
+                self.size += line.len() as u64 + 1;
+            }
+        }
+    }
+
+    /// Shift `.log` to `.log.1`, `.log.1` to `.log.2` and so on, dropping the oldest
+    fn rotate(&mut self) {
+        self.file = None;
+
+        for index in (1..LOG_FILES_KEPT).rev() {
+            let _ = fs::rename(numbered(&self.path, index), numbered(&self.path, index + 1));
+        }
+        let _ = fs::rename(&self.path, numbered(&self.path, 1));
+
+        *self = RotatingFile::open(self.path.clone());
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let entry = MessageEntry {
+            time: utc_timestamp(SystemTime::now()),
+            level: record.level().as_str(),
+            target: record.target(),
+            message: record.args().to_string(),
+        };
+
+        self.write(&entry);
+    }
+
+    fn flush(&self) {
+        if let Some(RotatingFile {
+            file: Some(file), ..
+        }) = &mut *self.file.lock().unwrap()
+        {
+            let _ = file.flush();
+        }
+    }
+}
+
+impl FileLogger {
+    fn write<T: Serialize>(&self, entry: &T) {
+        if let Ok(line) = serde_json::to_string(entry) {
+            if let Some(file) = &mut *self.file.lock().unwrap() {
+                file.write_line(&line);
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref LOGGER: FileLogger = FileLogger {
+        file: Mutex::new(None),
+    };
+}
+
+/// Start writing logs to `path` at the given verbosity
+pub fn init(path: PathBuf, level: LevelFilter) {
+    *LOGGER.file.lock().unwrap() = Some(RotatingFile::open(path));
+
+    if log::set_logger(&*LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+pub fn set_level(level: LevelFilter) {
+    log::set_max_level(level);
+}
+
+/// Record a handled request with its status, how long it took and the handler's error if any
+pub fn log_request(
+    method: &Method,
+    url: &str,
+    status: u16,
+    started: Instant,
+    error: Option<&ServerError>,
+) {
+    let level = match status {
+        500.. => Level::Error,
+        400..=499 => Level::Warn,
+        _ => Level::Info,
+    };
+
+    if level > log::max_level() {
+        return;
+    }
+
+    let entry = RequestEntry {
+        time: utc_timestamp(SystemTime::now()),
+        level: level.as_str(),
+        method: method.as_str(),
+        url,
+        status,
+        latency_ms: (started.elapsed().as_secs_f64() * 1000.0 * 100.0).round() / 100.0,
+        error: error.map(|error| ErrorEntry {
+            code: error.code,
+            message: &error.message,
+        }),
+    };
+
+    LOGGER.write(&entry);
+}
+
+pub fn flush() {
+    log::logger().flush();
+}
+
+fn numbered(path: &Path, index: usize) -> PathBuf {
+    let mut numbered = path.as_os_str().to_owned();
+    numbered.push(format!(".{}", index));
+    PathBuf::from(numbered)
+}
+
+/// Format a time as RFC 3339 in UTC with millisecond precision
+fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let days = (seconds / 86_400) as i64;
+    let seconds_of_day = seconds % 86_400;
+
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3_600,
+        seconds_of_day % 3_600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -1,80 +1,134 @@
 #![windows_subsystem = "windows"]
 #![allow(non_snake_case)]
 
-use crossbeam::channel::{unbounded, Receiver, Sender};
 use lazy_static::lazy_static;
-use serde::Serialize;
-use serde_json::from_reader;
 use std::{
-    collections::HashSet, env, ffi::OsStr, fs::File, io::Result as IoResult,
-    os::windows::ffi::OsStrExt, ptr, sync::Mutex, thread, time::Duration,
+    collections::HashSet,
+    io::Result as IoResult,
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
 };
-use tiny_http::{Server, StatusCode};
-use winapi::{
-    shared::winerror::ERROR_ALREADY_EXISTS, um::errhandlingapi::GetLastError,
-    um::synchapi::CreateMutexW,
-};
-
+use tiny_http::Server;
+
+mod access;
+mod animation;
+mod atlas;
+mod cache;
+mod conditional;
+mod config;
 mod constants;
+mod dds;
+mod errors;
+mod fonts;
+mod instance;
+mod logging;
+mod operations;
+mod pixels;
+mod pool;
 mod processes;
+mod router;
+mod shutdown;
+mod streaming;
+mod text;
+mod transform;
 mod utilities;
+mod watchdog;
+mod whitelist;
 mod handlers {
+    pub mod atlas;
+    pub mod cache;
+    pub mod config;
+    pub mod dds_cubemap;
     pub mod dds_image;
+    pub mod dds_info;
+    pub mod encode_dds;
     pub mod image;
+    pub mod image_tiles;
     pub mod list_directory;
+    pub mod pixels;
     pub mod process_running;
     pub mod run;
     pub mod shutdown;
+    pub mod sprite_sheet;
     pub mod stop_process;
+    pub mod text;
 }
 
-use constants::{Config, CONFIG_NAME, DEFAULT_PORT, MUTEX_NAME};
+use access::bind_host;
+use config::{config_path, report_errors, watch_config, ConfigState};
+use constants::{
+    DEFAULT_PORT, DEFAULT_QUEUE_DEPTH, DEFAULT_SHUTDOWN_TIMEOUT_SECONDS, DEFAULT_SLOW_WORKERS,
+    DEFAULT_WORKERS, LOG_NAME,
+};
+use errors::ServerError;
 use handlers::{
-    dds_image::handle_dds_image_request, image::handle_image_request,
-    list_directory::handle_list_directory, process_running::handle_process_running_request,
-    run::handle_run_request, shutdown::handle_shutdown_request,
+    atlas::{handle_atlas_map_request, handle_atlas_request},
+    cache::{handle_cache_purge_request, handle_cache_request},
+    config::handle_config_request,
+    dds_cubemap::handle_dds_cubemap_request,
+    dds_image::handle_dds_image_request,
+    dds_info::handle_dds_info_request,
+    encode_dds::handle_encode_dds_request,
+    image::handle_image_request,
+    image_tiles::handle_image_tiles_request,
+    list_directory::handle_list_directory,
+    pixels::handle_pixels_request,
+    process_running::handle_process_running_request,
+    run::handle_run_request,
+    shutdown::handle_shutdown_request,
+    sprite_sheet::{handle_sprite_sheet_map_request, handle_sprite_sheet_request},
     stop_process::handle_stop_process_request,
+    text::handle_text_request,
 };
-use processes::{is_darktide_running, is_process_running};
-use utilities::empty_response_with_status;
-
-#[derive(Serialize)]
-struct ProcessRunningResponse {
-    process_is_running: bool,
-}
+use instance::acquire_single_instance;
+use pool::WorkerPool;
+use processes::{terminate_created_processes, NativeBackend, ProcessBackend};
+use router::{respond, Router};
+use shutdown::{handle_signals, wait_for_shutdown};
+use utilities::executable_dir;
+use watchdog::watch_game;
 
 lazy_static! {
     static ref CREATED_PIDS: Mutex<HashSet<u32>> = Mutex::new(HashSet::new());
+    static ref PROCESS_BACKEND: NativeBackend = NativeBackend::default();
+    static ref CONFIG: RwLock<ConfigState> = RwLock::new(ConfigState::default());
 }
 
 fn main() -> IoResult<()> {
-    // Named mutex for single instance check
-    let mutex_name: Vec<u16> = OsStr::new(MUTEX_NAME)
-        .encode_wide()
-        .chain(std::iter::once(0))
-        .collect();
-    unsafe {
-        CreateMutexW(ptr::null_mut(), 0, mutex_name.as_ptr());
-        if GetLastError() == ERROR_ALREADY_EXISTS {
-            return Ok(());
-        }
+    if !acquire_single_instance() {
+        return Ok(());
     }
 
-    let mut bin_path = env::current_exe()?;
-    bin_path.pop(); // Get directory only, not executable itself
-    bin_path.push(CONFIG_NAME);
+    let config_path = config_path()?;
+    let mut config_state = ConfigState::load(&config_path);
 
-    let config: Config = match File::open(bin_path) {
-        Ok(file) => from_reader(file).unwrap_or_default(),
-        Err(_) => Default::default(),
-    };
+    logging::init(
+        executable_dir()?.join(LOG_NAME),
+        config_state.config.log_level(),
+    );
+    log::info!("Starting DarktideLocalServer {}", env!("CARGO_PKG_VERSION"));
+    report_errors(&config_state.errors);
 
-    let port = config.port.unwrap_or(DEFAULT_PORT);
+    let port = config_state.config.port.unwrap_or(DEFAULT_PORT);
+    let host = bind_host(&config_state.config);
+
+    if !host.is_loopback() {
+        log::warn!(
+            "Binding to {}: server is reachable from other machines allowed by allowed_clients",
+            host
+        );
+    }
 
-    let server = match Server::http(format!("0.0.0.0:{}", port)) {
-        Ok(server) => server,
+    let server = match Server::http(SocketAddr::new(host, port)) {
+        Ok(server) => {
+            log::info!("Listening on {}:{}", host, port);
+            server
+        }
         Err(err) => {
-            eprintln!("Failed to create server: {}", err);
+            log::error!("Failed to create server: {}", err);
+            logging::flush();
             return Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 "Failed to create server",
@@ -82,86 +136,119 @@ fn main() -> IoResult<()> {
         }
     };
 
-    let (process_running_sender, process_running_receiver): (
-        Sender<tiny_http::Request>,
-        Receiver<tiny_http::Request>,
-    ) = unbounded();
-
-    thread::spawn(|| loop {
-        if !is_darktide_running() {
-            println!("Darktide.exe is not running. Shutting down.");
-            std::process::exit(1);
-        }
-        thread::sleep(Duration::from_secs(1));
-    });
-
-    // Thread to handle /process_running requests
-    thread::spawn(move || {
-        for request in process_running_receiver.iter() {
-            let response = handle_process_running_request(&request, is_process_running);
-            let _ = request
-                .respond(response.unwrap_or_else(|_| empty_response_with_status(StatusCode(400))));
-        }
-    });
-
-    // Main thread for other requests
-    thread::spawn(move || {
-        for mut request in server.incoming_requests() {
-            let url = request.url().to_string();
-            let method = request.method().to_string();
-
-            if method == "GET" {
-                if url.starts_with("/dds_image") {
-                    let response = handle_dds_image_request(&request)
-                        .unwrap_or_else(|| empty_response_with_status(StatusCode(400)));
-                    let _ = request.respond(response);
-                    continue;
-                }
-
-                if url.starts_with("/image") {
-                    let response = handle_image_request(&request)
-                        .unwrap_or_else(|| empty_response_with_status(StatusCode(400)));
-                    let _ = request.respond(response);
+    let startup = config_state.config.resolved();
+    let workers = startup.workers.unwrap_or(DEFAULT_WORKERS);
+    let slow_workers = startup.slow_workers.unwrap_or(DEFAULT_SLOW_WORKERS);
+    let queue_depth = startup.queue_depth.unwrap_or(DEFAULT_QUEUE_DEPTH);
+
+    config_state.startup = Some(startup);
+    *CONFIG.write().unwrap() = config_state;
+    watch_config(config_path);
+
+    watch_game(&*PROCESS_BACKEND);
+    handle_signals();
+
+    let mut router = Router::new();
+
+    router
+        .get_file("/atlas", |request| handle_atlas_request(request))
+        .get_slow("/atlas_map", |request| handle_atlas_map_request(request))
+        .get("/cache", |_request| handle_cache_request())
+        .post("/cache/purge", |request| {
+            handle_cache_purge_request(request)
+        })
+        .get("/config", |_request| handle_config_request())
+        .get_file("/dds_cubemap", |request| {
+            handle_dds_cubemap_request(request)
+        })
+        .get_file("/dds_image", |request| handle_dds_image_request(request))
+        .get("/dds_info", |request| handle_dds_info_request(request))
+        .post_slow("/encode_dds", |request| handle_encode_dds_request(request))
+        .get_file("/image", |request| handle_image_request(request))
+        .get("/image_tiles", |request| {
+            handle_image_tiles_request(request)
+        })
+        .get_slow("/list_directory", |request| handle_list_directory(request))
+        .get_file("/pixels", |request| handle_pixels_request(request))
+        .get("/process_running", |request| {
+            handle_process_running_request(request, |pid| PROCESS_BACKEND.is_running(pid))
+        })
+        .get("/shutdown", |_request| handle_shutdown_request())
+        .get_file("/sprite_sheet", |request| {
+            handle_sprite_sheet_request(request)
+        })
+        .get_slow("/sprite_sheet_map", |request| {
+            handle_sprite_sheet_map_request(request)
+        })
+        .get("/stop_process", |request| {
+            handle_stop_process_request(request, &*PROCESS_BACKEND)
+        })
+        .get_file("/text", |request| handle_text_request(request))
+        .post("/run", |request| {
+            handle_run_request(request, &*PROCESS_BACKEND)
+        });
+
+    let router = Arc::new(router);
+    let pool = Arc::new(WorkerPool::new(
+        Arc::clone(&router),
+        workers,
+        slow_workers,
+        queue_depth,
+    ));
+    let server = Arc::new(server);
+
+    // Accept connections and hand them to the workers
+    let acceptor = {
+        let server = Arc::clone(&server);
+        let pool = Arc::clone(&pool);
+
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let started = Instant::now();
+                let allowed = CONFIG
+                    .read()
+                    .unwrap()
+                    .allowed_clients
+                    .allows(request.remote_addr());
+
+                if !allowed {
+                    let error = ServerError::forbidden("Client address is not allowed");
+                    respond(request, Err(error), started);
                     continue;
                 }
 
-                if url.starts_with("/list_directory") {
-                    let response = handle_list_directory(&request)
-                        .unwrap_or_else(|| empty_response_with_status(StatusCode(400)));
-                    let _ = request.respond(response);
-                    continue;
-                }
-
-                if url.starts_with("/process_running") {
-                    process_running_sender.send(request).unwrap();
-                    continue;
-                }
-
-                if url == "/shutdown" {
-                    handle_shutdown_request();
-                }
-
-                if url.starts_with("/stop_process") {
-                    let response = handle_stop_process_request(&request);
-                    let _ = request.respond(response);
-                    continue;
+                if let Some((request, route)) = router.resolve(request, started) {
+                    pool.submit(request, route, router.lane(route), started);
                 }
             }
+        })
+    };
 
-            if method == "POST" {
-                if url.starts_with("/run") {
-                    let response = handle_run_request(&mut request)
-                        .unwrap_or_else(|status| empty_response_with_status(status));
-                    let _ = request.respond(response);
-                    continue;
-                }
-            }
-
-            let _ = request.respond(empty_response_with_status(StatusCode(400)));
-        }
-    });
-
-    loop {
-        thread::sleep(Duration::from_secs(60));
+    let reason = wait_for_shutdown();
+    log::info!("Shutting down: {}", reason);
+
+    // Stop accepting connections, closing the listener once the acceptor has let go of it
+    server.unblock();
+    let _ = acceptor.join();
+    drop(server);
+
+    let timeout = CONFIG
+        .read()
+        .unwrap()
+        .config
+        .shutdown_timeout_seconds
+        .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECONDS);
+    let remaining = pool.drain(Duration::from_secs(timeout));
+    if remaining > 0 {
+        log::warn!(
+            "Gave up waiting for {} requests after {} seconds",
+            remaining,
+            timeout
+        );
     }
+
+    terminate_created_processes(&*PROCESS_BACKEND);
+    log::info!("Exiting with code {}", reason.code());
+    logging::flush();
+    std::process::exit(reason.code());
 }
@@ -0,0 +1,462 @@
+use image::{DynamicImage, Rgb, Rgba, RgbaImage};
+use std::{collections::HashMap, fs::File, io::Read};
+
+use crate::constants::{MAX_OPERATIONS, OPERATION_SEPARATOR};
+use crate::errors::ServerError;
+use crate::transform::parse_colour;
+
+/// EXIF tag holding how the camera was held
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Bytes read from the start of a file to find its EXIF orientation
+const HEADER_BYTES: u64 = 128 * 1024;
+
+/// One step of the `ops` query parameter, e.g. `crop:0,0,256,256|mask:circle`
+pub enum Operation {
+    /// Cut out a rectangle given by its left, top, width and height
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Cut the largest square out of the centre
+    CropSquare,
+    /// Turn clockwise by 90, 180 or 270 degrees
+    Rotate(u32),
+    FlipHorizontal,
+    FlipVertical,
+    /// Make everything outside the largest centred circle transparent
+    CircleMask,
+    Grayscale,
+    /// Multiply every pixel by a colour
+    Tint(Rgb<u8>),
+}
+
+/// Parse the `ops` query parameter, returning no operations when it is missing
+pub fn parse_operations(params: &HashMap<String, String>) -> Result<Vec<Operation>, ServerError> {
+    let Some(value) = params.get("ops") else {
+        return Ok(Vec::new());
+    };
+
+    let operations = value
+        .split(OPERATION_SEPARATOR)
+        .map(str::trim)
+        .filter(|step| !step.is_empty())
+        .map(parse_operation)
+        .collect::<Result<Vec<Operation>, ServerError>>()?;
+
+    if operations.len() > MAX_OPERATIONS {
+        return Err(ServerError::bad_request(format!(
+            "ops can have at most {} steps",
+            MAX_OPERATIONS
+        )));
+    }
+
+    Ok(operations)
+}
+
+fn parse_operation(step: &str) -> Result<Operation, ServerError> {
+    let (name, argument) = step.split_once(':').unwrap_or((step, ""));
+    let invalid = || ServerError::invalid_parameter("ops", step);
+
+    let operation = match (
+        name.to_lowercase().as_str(),
+        argument.to_lowercase().as_str(),
+    ) {
+        ("crop", "square") => Operation::CropSquare,
+        ("crop", argument) => {
+            let numbers = argument
+                .split(',')
+                .map(|number| number.trim().parse::<u32>())
+                .collect::<Result<Vec<u32>, _>>()
+                .map_err(|_| invalid())?;
+
+            match numbers[..] {
+                [x, y, width, height] if width > 0 && height > 0 => Operation::Crop {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        ("rotate", "90" | "-270") => Operation::Rotate(90),
+        ("rotate", "180" | "-180") => Operation::Rotate(180),
+        ("rotate", "270" | "-90") => Operation::Rotate(270),
+        ("flip", "h" | "horizontal") => Operation::FlipHorizontal,
+        ("flip", "v" | "vertical") => Operation::FlipVertical,
+        ("mask", "circle") => Operation::CircleMask,
+        ("grayscale" | "greyscale", "") => Operation::Grayscale,
+        ("tint", colour) => Operation::Tint(parse_colour(colour).ok_or_else(invalid)?),
+        _ => return Err(invalid()),
+    };
+
+    Ok(operation)
+}
+
+/// Apply operations in order
+pub fn apply_operations(
+    mut image: DynamicImage,
+    operations: &[Operation],
+) -> Result<DynamicImage, ServerError> {
+    for operation in operations {
+        image = match *operation {
+            Operation::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let fits = u64::from(x) + u64::from(width) <= u64::from(image.width())
+                    && u64::from(y) + u64::from(height) <= u64::from(image.height());
+                if !fits {
+                    return Err(ServerError::out_of_range(format!(
+                        "crop {},{},{},{} is outside the {}x{} image",
+                        x,
+                        y,
+                        width,
+                        height,
+                        image.width(),
+                        image.height()
+                    )));
+                }
+                image.crop_imm(x, y, width, height)
+            }
+            Operation::CropSquare => {
+                let size = image.width().min(image.height());
+                image.crop_imm(
+                    (image.width() - size) / 2,
+                    (image.height() - size) / 2,
+                    size,
+                    size,
+                )
+            }
+            Operation::Rotate(90) => image.rotate90(),
+            Operation::Rotate(180) => image.rotate180(),
+            Operation::Rotate(_) => image.rotate270(),
+            Operation::FlipHorizontal => image.fliph(),
+            Operation::FlipVertical => image.flipv(),
+            Operation::CircleMask => DynamicImage::ImageRgba8(circle_mask(&image)),
+            Operation::Grayscale => image.grayscale(),
+            Operation::Tint(colour) => DynamicImage::ImageRgba8(tint(&image, colour)),
+        };
+    }
+
+    Ok(image)
+}
+
+/// Turn an image upright according to its EXIF orientation, from 1 (upright) to 8
+pub fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Read the EXIF orientation from the start of the file at `path`, 1 if it has none
+pub fn file_orientation(path: &str) -> Result<u16, ServerError> {
+    let mut header = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(HEADER_BYTES).read_to_end(&mut header))
+        .map_err(|err| ServerError::from_io(&err, path))?;
+
+    Ok(exif_orientation(&header).unwrap_or(1))
+}
+
+/// Read the EXIF orientation of a JPEG or PNG, if it has one
+pub fn exif_orientation(bytes: &[u8]) -> Option<u16> {
+    let exif = if bytes.starts_with(&[0xFF, 0xD8]) {
+        jpeg_exif(bytes)?
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_exif(bytes)?
+    } else {
+        return None;
+    };
+
+    tiff_orientation(exif).filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Find the TIFF structure in a JPEG's APP1 segment, stopping at the image data
+fn jpeg_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut offset = 2;
+
+    while offset + 4 <= bytes.len() {
+        if bytes[offset] != 0xFF {
+            return None;
+        }
+        let marker = bytes[offset + 1];
+
+        // Start of scan, after which there are no more headers
+        if marker == 0xDA {
+            return None;
+        }
+
+        let length = usize::from(u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]));
+        let segment = bytes.get(offset + 4..offset + 2 + length)?;
+
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+
+        offset += 2 + length;
+    }
+
+    None
+}
+
+/// Find the TIFF structure in a PNG's eXIf chunk
+fn png_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut offset = 8;
+
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into().ok()?) as usize;
+        let kind = &bytes[offset + 4..offset + 8];
+        let data = bytes.get(offset + 8..offset + 8 + length)?;
+
+        match kind {
+            b"eXIf" => return Some(data),
+            b"IEND" => return None,
+            _ => offset += 12 + length,
+        }
+    }
+
+    None
+}
+
+/// Read the orientation tag from the first IFD of a TIFF structure
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+
+    let read_u16 = |offset: usize| {
+        let bytes: [u8; 2] = tiff.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let read_u32 = |offset: usize| {
+        let bytes: [u8; 4] = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)?;
+
+    (0..usize::from(entries))
+        .map(|entry| ifd + 2 + entry * 12)
+        .find(|&entry| read_u16(entry) == Some(ORIENTATION_TAG))
+        .and_then(|entry| read_u16(entry + 8))
+}
+
+fn circle_mask(image: &DynamicImage) -> RgbaImage {
+    let mut masked = image.to_rgba8();
+    let (width, height) = masked.dimensions();
+    let radius = width.min(height) as f32 / 2.0;
+    let (centre_x, centre_y) = (width as f32 / 2.0, height as f32 / 2.0);
+
+    for (x, y, pixel) in masked.enumerate_pixels_mut() {
+        let distance =
+            ((x as f32 + 0.5 - centre_x).powi(2) + (y as f32 + 0.5 - centre_y).powi(2)).sqrt();
+
+        // Fade across the pixel on the edge so the circle is antialiased
+        let coverage = (radius - distance + 0.5).clamp(0.0, 1.0);
+        pixel[3] = (f32::from(pixel[3]) * coverage).round() as u8;
+    }
+
+    masked
+}
+
+fn tint(image: &DynamicImage, colour: Rgb<u8>) -> RgbaImage {
+    let mut tinted = image.to_rgba8();
+
+    for pixel in tinted.pixels_mut() {
+        let Rgba([red, green, blue, alpha]) = *pixel;
+        let multiply = |value: u8, channel: usize| {
+            ((u16::from(value) * u16::from(colour[channel]) + 127) / 255) as u8
+        };
+        *pixel = Rgba([
+            multiply(red, 0),
+            multiply(green, 1),
+            multiply(blue, 2),
+            alpha,
+        ]);
+    }
+
+    tinted
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    /// Maps a pixel of an upright image to the pixel it came from
+    type SourcePixel = fn(u32, u32) -> (u32, u32);
+
+    /// A TIFF structure whose first IFD holds only the orientation tag
+    fn tiff(byte_order: &[u8; 2], orientation: u16) -> Vec<u8> {
+        let little_endian = byte_order == b"II";
+        let u16_bytes = |value: u16| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+
+        let mut tiff = byte_order.to_vec();
+        tiff.extend(u16_bytes(42));
+        tiff.extend(u32_bytes(8));
+        tiff.extend(u16_bytes(1));
+        tiff.extend(u16_bytes(ORIENTATION_TAG));
+        tiff.extend(u16_bytes(3));
+        tiff.extend(u32_bytes(1));
+        tiff.extend(u16_bytes(orientation));
+        tiff.extend([0, 0]);
+        tiff.extend(u32_bytes(0));
+        tiff
+    }
+
+    fn jpeg_segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend((data.len() as u16 + 2).to_be_bytes());
+        segment.extend(data);
+        segment
+    }
+
+    fn jpeg(tiff: &[u8]) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend(jpeg_segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0"));
+        jpeg.extend(jpeg_segment(0xE1, &[&b"Exif\0\0"[..], tiff].concat()));
+        jpeg.extend(jpeg_segment(0xDA, &[0; 10]));
+        jpeg
+    }
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend(kind);
+        chunk.extend(data);
+        // The CRC is not checked when looking for EXIF
+        chunk.extend([0; 4]);
+        chunk
+    }
+
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(png_chunk(b"IHDR", &[0; 13]));
+        for chunk in chunks {
+            png.extend(chunk);
+        }
+        png.extend(png_chunk(b"IEND", &[]));
+        png
+    }
+
+    #[test]
+    fn reads_both_byte_orders() {
+        assert_eq!(exif_orientation(&jpeg(&tiff(b"II", 6))), Some(6));
+        assert_eq!(exif_orientation(&jpeg(&tiff(b"MM", 8))), Some(8));
+        assert_eq!(exif_orientation(&jpeg(&tiff(b"XX", 6))), None);
+    }
+
+    #[test]
+    fn reads_png_exif_chunks() {
+        let with_exif = png(&[png_chunk(b"eXIf", &tiff(b"MM", 3))]);
+        assert_eq!(exif_orientation(&with_exif), Some(3));
+        assert_eq!(exif_orientation(&png(&[])), None);
+    }
+
+    #[test]
+    fn ignores_orientations_outside_the_standard_eight() {
+        assert_eq!(exif_orientation(&jpeg(&tiff(b"II", 0))), None);
+        assert_eq!(exif_orientation(&jpeg(&tiff(b"II", 9))), None);
+    }
+
+    #[test]
+    fn truncated_files_have_no_orientation() {
+        let tiff = tiff(b"II", 6);
+        let jpeg = jpeg(&tiff);
+        let app1_end = jpeg.len() - 14;
+        for end in 0..app1_end {
+            assert_eq!(exif_orientation(&jpeg[..end]), None, "JPEG cut at {}", end);
+        }
+
+        let png = png(&[png_chunk(b"eXIf", &tiff)]);
+        let exif_end = png.len() - 16;
+        for end in 0..exif_end {
+            assert_eq!(exif_orientation(&png[..end]), None, "PNG cut at {}", end);
+        }
+    }
+
+    #[test]
+    fn segment_lengths_below_two_are_rejected() {
+        for length in [0u8, 1] {
+            let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0, length];
+            jpeg.extend(jpeg_segment(
+                0xE1,
+                &[&b"Exif\0\0"[..], &tiff(b"II", 6)].concat(),
+            ));
+            assert_eq!(exif_orientation(&jpeg), None);
+        }
+    }
+
+    #[test]
+    fn turns_transposed_orientations_upright() {
+        let (width, height) = (3, 2);
+        let image = RgbImage::from_fn(width, height, |x, y| image::Rgb([x as u8, y as u8, 0]));
+
+        let sources: [(u16, SourcePixel); 4] = [
+            (5, |x, y| (y, x)),
+            (6, |x, y| (y, 1 - x)),
+            (7, |x, y| (2 - y, 1 - x)),
+            (8, |x, y| (2 - y, x)),
+        ];
+
+        for (orientation, source) in sources {
+            let upright =
+                apply_orientation(DynamicImage::ImageRgb8(image.clone()), orientation).to_rgb8();
+            assert_eq!(
+                upright.dimensions(),
+                (height, width),
+                "orientation {}",
+                orientation
+            );
+
+            for (x, y, pixel) in upright.enumerate_pixels() {
+                let (source_x, source_y) = source(x, y);
+                assert_eq!(
+                    pixel.0,
+                    [source_x as u8, source_y as u8, 0],
+                    "orientation {} at {},{}",
+                    orientation,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use color_quant::NeuQuant;
+use image::RgbaImage;
+use std::collections::HashMap;
+
+use crate::constants::{DEFAULT_PALETTE_COLOURS, MAX_PALETTE_COLOURS, MIN_PALETTE_COLOURS};
+use crate::errors::ServerError;
+use crate::utilities::bounded_param;
+
+/// Sampling factor of the quantiser, from 1 (slowest, best) to 30
+const QUANTISER_SAMPLING: i32 = 10;
+
+/// Longest run of one colour in run-length encoded rows, so its length fits in a byte
+const MAX_RUN: usize = 255;
+
+/// Layout of the pixels in the response body
+#[derive(Clone, Copy, PartialEq)]
+pub enum PixelFormat {
+    /// Four bytes per pixel, row by row from the top left
+    Rgba8,
+    /// A palette of RGBA colours followed by one palette index per pixel
+    Palette,
+    /// Runs of a length byte and an RGBA colour, never spanning two rows
+    Rle,
+}
+
+/// Form of the pixels to return, read from `format` and `colours` query parameters
+pub struct PixelRequest {
+    pub format: PixelFormat,
+    /// Most colours a palette may hold, only used by `PixelFormat::Palette`
+    pub colours: u32,
+}
+
+/// An image's pixels in the requested form
+pub struct PixelBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    /// Number of colours at the start of `bytes`, only for `PixelFormat::Palette`
+    pub palette_size: Option<u32>,
+    pub bytes: Vec<u8>,
+}
+
+impl PixelFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Rgba8 => "rgba8",
+            PixelFormat::Palette => "palette",
+            PixelFormat::Rle => "rle",
+        }
+    }
+}
+
+impl PixelRequest {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<PixelRequest, ServerError> {
+        let format = match params.get("format").map(|value| value.to_lowercase()) {
+            None => PixelFormat::Rgba8,
+            Some(value) => match value.as_str() {
+                "rgba" | "rgba8" => PixelFormat::Rgba8,
+                "palette" => PixelFormat::Palette,
+                "rle" => PixelFormat::Rle,
+                _ => return Err(ServerError::invalid_parameter("format", &value)),
+            },
+        };
+
+        Ok(PixelRequest {
+            format,
+            colours: bounded_param(
+                params,
+                "colours",
+                DEFAULT_PALETTE_COLOURS,
+                MIN_PALETTE_COLOURS,
+                MAX_PALETTE_COLOURS,
+            )?,
+        })
+    }
+
+    pub fn encode(&self, image: RgbaImage) -> PixelBuffer {
+        let (width, height) = image.dimensions();
+
+        let (palette_size, bytes) = match self.format {
+            PixelFormat::Rgba8 => (None, image.into_raw()),
+            PixelFormat::Palette => {
+                let (palette, indexes) = quantise(&image, self.colours as usize);
+                let palette_size = (palette.len() / 4) as u32;
+                (Some(palette_size), [palette, indexes].concat())
+            }
+            PixelFormat::Rle => (None, run_length_encode(&image)),
+        };
+
+        PixelBuffer {
+            width,
+            height,
+            format: self.format,
+            palette_size,
+            bytes,
+        }
+    }
+}
+
+/// Return a palette of RGBA colours and each pixel's index into it
+///
+/// Images with few enough colours, like most pixel art, keep them exactly.
+fn quantise(image: &RgbaImage, colours: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut lookup: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut indexes = Vec::with_capacity((image.width() * image.height()) as usize);
+
+    for pixel in image.pixels() {
+        let index = match lookup.get(&pixel.0) {
+            Some(index) => *index,
+            None if palette.len() < colours => {
+                let index = palette.len() as u8;
+                palette.push(pixel.0);
+                lookup.insert(pixel.0, index);
+                index
+            }
+            None => return quantise_lossy(image, colours),
+        };
+        indexes.push(index);
+    }
+
+    (palette.concat(), indexes)
+}
+
+/// Reduce an image with more colours than the palette may hold to its closest `colours`
+fn quantise_lossy(image: &RgbaImage, colours: usize) -> (Vec<u8>, Vec<u8>) {
+    let quantiser = NeuQuant::new(QUANTISER_SAMPLING, colours, image.as_raw());
+    let indexes = image
+        .pixels()
+        .map(|pixel| quantiser.index_of(&pixel.0) as u8)
+        .collect();
+
+    (quantiser.color_map_rgba(), indexes)
+}
+
+fn run_length_encode(image: &RgbaImage) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    for row in image.rows() {
+        let mut run: Option<([u8; 4], usize)> = None;
+
+        for pixel in row {
+            run = match run {
+                Some((colour, length)) if colour == pixel.0 && length < MAX_RUN => {
+                    Some((colour, length + 1))
+                }
+                Some((colour, length)) => {
+                    push_run(&mut bytes, colour, length);
+                    Some((pixel.0, 1))
+                }
+                None => Some((pixel.0, 1)),
+            };
+        }
+
+        if let Some((colour, length)) = run {
+            push_run(&mut bytes, colour, length);
+        }
+    }
+
+    bytes
+}
+
+fn push_run(bytes: &mut Vec<u8>, colour: [u8; 4], length: usize) {
+    bytes.push(length as u8);
+    bytes.extend_from_slice(&colour);
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    fn palette_request(colours: u32) -> PixelRequest {
+        PixelRequest {
+            format: PixelFormat::Palette,
+            colours,
+        }
+    }
+
+    /// Split run-length encoded bytes into (length, colour) runs
+    fn runs(bytes: &[u8]) -> Vec<(u8, [u8; 4])> {
+        bytes
+            .chunks(5)
+            .map(|run| (run[0], [run[1], run[2], run[3], run[4]]))
+            .collect()
+    }
+
+    #[test]
+    fn runs_split_at_255() {
+        let image = RgbaImage::from_pixel(600, 1, Rgba(RED));
+        assert_eq!(
+            runs(&run_length_encode(&image)),
+            vec![(255, RED), (255, RED), (90, RED)]
+        );
+    }
+
+    #[test]
+    fn runs_do_not_cross_rows() {
+        let mut image = RgbaImage::from_pixel(3, 2, Rgba(RED));
+        image.put_pixel(0, 0, Rgba(CLEAR));
+        assert_eq!(
+            runs(&run_length_encode(&image)),
+            vec![(1, CLEAR), (2, RED), (3, RED)]
+        );
+    }
+
+    #[test]
+    fn few_colours_keep_an_exact_palette() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let buffer = palette_request(256).encode(image.clone());
+
+        assert_eq!(buffer.palette_size, Some(256));
+        let (palette, indexes) = buffer.bytes.split_at(256 * 4);
+        for (pixel, index) in image.pixels().zip(indexes) {
+            let colour = &palette[usize::from(*index) * 4..][..4];
+            assert_eq!(colour, pixel.0);
+        }
+    }
+
+    #[test]
+    fn palette_lists_colours_in_order_of_appearance() {
+        let mut image = RgbaImage::from_pixel(2, 2, Rgba(RED));
+        image.put_pixel(1, 0, Rgba(CLEAR));
+        let buffer = palette_request(16).encode(image);
+
+        assert_eq!(buffer.palette_size, Some(2));
+        assert_eq!(buffer.bytes, [&RED[..], &CLEAR, &[0, 1, 0, 0]].concat());
+    }
+
+    #[test]
+    fn too_many_colours_are_reduced_to_the_palette_size() {
+        let image = RgbaImage::from_fn(32, 32, |x, y| Rgba([x as u8 * 8, y as u8 * 8, 128, 255]));
+        let buffer = palette_request(16).encode(image.clone());
+
+        assert_eq!(buffer.palette_size, Some(16));
+        let (palette, indexes) = buffer.bytes.split_at(16 * 4);
+        assert_eq!(indexes.len(), 32 * 32);
+
+        for (pixel, index) in image.pixels().zip(indexes) {
+            assert!(*index < 16);
+            let colour = &palette[usize::from(*index) * 4..][..4];
+            let distance = colour
+                .iter()
+                .zip(pixel.0)
+                .map(|(a, b)| a.abs_diff(b))
+                .max()
+                .unwrap();
+            assert!(distance < 96, "{:?} became {:?}", pixel.0, colour);
+        }
+    }
+}
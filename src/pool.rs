@@ -0,0 +1,125 @@
+use crossbeam::channel::{bounded, Receiver, Sender, TrySendError};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tiny_http::{Header, Request};
+
+use crate::constants::RETRY_AFTER_SECONDS;
+use crate::errors::ServerError;
+use crate::logging::log_request;
+use crate::router::{Lane, Router};
+
+type Job = (Request, usize, Instant);
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Fixed sets of worker threads with bounded queues, one set per route lane
+pub struct WorkerPool {
+    fast: Sender<Job>,
+    slow: Sender<Job>,
+    /// Requests queued or being handled
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl WorkerPool {
+    pub fn new(
+        router: Arc<Router>,
+        fast_workers: usize,
+        slow_workers: usize,
+        queue_depth: usize,
+    ) -> Self {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+
+        WorkerPool {
+            fast: spawn_workers(&router, &in_flight, "fast", fast_workers, queue_depth),
+            slow: spawn_workers(&router, &in_flight, "slow", slow_workers, queue_depth),
+            in_flight,
+        }
+    }
+
+    /// Queue a resolved request, answering 503 if its lane's queue is full
+    pub fn submit(&self, request: Request, route: usize, lane: Lane, started: Instant) {
+        let sender = match lane {
+            Lane::Fast => &self.fast,
+            Lane::Slow => &self.slow,
+        };
+
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        match sender.try_send((request, route, started)) {
+            Ok(()) => {}
+            Err(TrySendError::Full((request, _, _)))
+            | Err(TrySendError::Disconnected((request, _, _))) => {
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                let error = ServerError::service_unavailable("Server is busy, try again shortly");
+                log_request(
+                    request.method(),
+                    request.url(),
+                    error.status,
+                    started,
+                    Some(&error),
+                );
+                let retry_after = Header::from_bytes(
+                    &b"Retry-After"[..],
+                    RETRY_AFTER_SECONDS.to_string().as_bytes(),
+                )
+                .unwrap();
+                let _ = request.respond(error.into_response().with_header(retry_after));
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for queued and running requests to finish, returning how many are left
+    pub fn drain(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = self.in_flight.load(Ordering::SeqCst);
+            if remaining == 0 || Instant::now() >= deadline {
+                return remaining;
+            }
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Counts a request as finished when dropped, even if handling it unwinds
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn spawn_workers(
+    router: &Arc<Router>,
+    in_flight: &Arc<AtomicUsize>,
+    name: &str,
+    count: usize,
+    queue_depth: usize,
+) -> Sender<Job> {
+    let (sender, receiver): (Sender<Job>, Receiver<Job>) = bounded(queue_depth);
+
+    for index in 0..count {
+        let router = Arc::clone(router);
+        let in_flight = Arc::clone(in_flight);
+        let receiver = receiver.clone();
+
+        let _ = thread::Builder::new()
+            .name(format!("{}-worker-{}", name, index))
+            .spawn(move || {
+                for (request, route, started) in receiver.iter() {
+                    let _finished = InFlightGuard(&in_flight);
+                    router.handle(route, request, started);
+                }
+            });
+    }
+
+    sender
+}
@@ -1,40 +1,67 @@
-// use sysinfo::{Pid, Process, ProcessExt, System, SystemExt};
-use sysinfo::{Pid, ProcessExt, System, SystemExt};
-use winapi::um::{
-    handleapi::CloseHandle, processthreadsapi::OpenProcess, processthreadsapi::TerminateProcess,
-    winnt::PROCESS_TERMINATE,
-};
-
-pub fn is_darktide_running() -> bool {
-    let mut sys = System::new_all();
-    sys.refresh_all();
-
-    for (_pid, proc_) in sys.processes() {
-        if proc_.name() == "Darktide.exe" {
-            return true;
-        }
-    }
+use std::io::Result as IoResult;
+use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
+
+#[cfg(test)]
+pub mod mock;
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(unix)]
+pub use unix::UnixBackend as NativeBackend;
+#[cfg(windows)]
+pub use windows::WindowsBackend as NativeBackend;
+
+use crate::CREATED_PIDS;
+
+/// Operating system operations used to start, inspect and stop processes
+pub trait ProcessBackend: Send + Sync {
+    /// Start an executable in the background and return its PID
+    fn spawn(&self, executable: &str, args: &[String]) -> IoResult<u32>;
+
+    /// Whether a process with the given PID exists
+    fn is_running(&self, pid: u32) -> bool;
 
-    false
+    /// Forcefully stop a process, returning whether it was terminated
+    fn terminate(&self, pid: u32) -> bool;
+
+    /// Return the PIDs of every process with the given name, ignoring case
+    fn find_by_name(&self, name: &str) -> Vec<u32>;
 }
 
-pub fn is_process_running(pid: Pid) -> bool {
-    let mut sys = System::new_all();
-    sys.refresh_all();
-    sys.processes().contains_key(&(pid))
+/// Whether a process with any of the given names is running
+pub fn is_any_running(backend: &dyn ProcessBackend, names: &[String]) -> bool {
+    names
+        .iter()
+        .any(|name| !backend.find_by_name(name).is_empty())
 }
 
-pub fn stop_process(pid: u32) -> bool {
-    unsafe {
-        let h_process = OpenProcess(PROCESS_TERMINATE, 0, pid);
+/// Stop every process started through /run that is still running
+pub fn terminate_created_processes(backend: &dyn ProcessBackend) {
+    let mut pids = CREATED_PIDS.lock().unwrap();
 
-        if h_process.is_null() {
-            return false;
+    for pid in pids.drain() {
+        if backend.is_running(pid) && !backend.terminate(pid) {
+            log::warn!("Could not terminate process {}", pid);
         }
+    }
+}
 
-        let success = TerminateProcess(h_process, 1);
-        CloseHandle(h_process);
+/// Process lookup by PID shared by the native backends
+fn system_has_process(pid: u32) -> bool {
+    let mut sys = System::new();
+    sys.refresh_process(Pid::from_u32(pid))
+}
 
-        success != 0
-    }
+/// Process lookup by name shared by the native backends
+fn system_find_by_name(name: &str) -> Vec<u32> {
+    let mut sys = System::new();
+    sys.refresh_processes();
+
+    sys.processes()
+        .iter()
+        .filter(|(_pid, proc_)| proc_.name().eq_ignore_ascii_case(name))
+        .map(|(pid, _proc)| pid.as_u32())
+        .collect()
 }
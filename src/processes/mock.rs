@@ -0,0 +1,109 @@
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    path::Path,
+    sync::Mutex,
+};
+
+use super::ProcessBackend;
+
+const FIRST_MOCK_PID: u32 = 1000;
+
+/// In-memory process table for exercising handlers without starting real processes
+pub struct MockBackend {
+    state: Mutex<MockState>,
+}
+
+struct MockState {
+    processes: HashMap<u32, String>,
+    next_pid: u32,
+    fail_spawn: bool,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        MockBackend {
+            state: Mutex::new(MockState {
+                processes: HashMap::new(),
+                next_pid: FIRST_MOCK_PID,
+                fail_spawn: false,
+            }),
+        }
+    }
+
+    /// Add a process that is already running, e.g. "Darktide.exe"
+    pub fn with_process(self, pid: u32, name: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .processes
+            .insert(pid, name.to_string());
+        self
+    }
+
+    /// Make every subsequent `spawn` fail as if the executable was not found
+    pub fn failing_spawn(self) -> Self {
+        self.state.lock().unwrap().fail_spawn = true;
+        self
+    }
+
+    /// Names of the processes currently in the table, sorted by PID
+    pub fn running(&self) -> Vec<(u32, String)> {
+        let state = self.state.lock().unwrap();
+        let mut running: Vec<(u32, String)> = state
+            .processes
+            .iter()
+            .map(|(pid, name)| (*pid, name.clone()))
+            .collect();
+        running.sort();
+        running
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessBackend for MockBackend {
+    fn spawn(&self, executable: &str, _args: &[String]) -> IoResult<u32> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.fail_spawn {
+            return Err(IoError::new(ErrorKind::NotFound, "mock spawn failure"));
+        }
+
+        let name = Path::new(executable)
+            .file_name()
+            .and_then(|os_str| os_str.to_str())
+            .unwrap_or(executable)
+            .to_string();
+
+        let pid = state.next_pid;
+        state.next_pid += 1;
+        state.processes.insert(pid, name);
+
+        Ok(pid)
+    }
+
+    fn is_running(&self, pid: u32) -> bool {
+        self.state.lock().unwrap().processes.contains_key(&pid)
+    }
+
+    fn terminate(&self, pid: u32) -> bool {
+        self.state.lock().unwrap().processes.remove(&pid).is_some()
+    }
+
+    fn find_by_name(&self, name: &str) -> Vec<u32> {
+        let state = self.state.lock().unwrap();
+        let mut pids: Vec<u32> = state
+            .processes
+            .iter()
+            .filter(|(_pid, process_name)| process_name.eq_ignore_ascii_case(name))
+            .map(|(pid, _name)| *pid)
+            .collect();
+        pids.sort_unstable();
+        pids
+    }
+}
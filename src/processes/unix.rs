@@ -0,0 +1,67 @@
+use std::{
+    collections::HashMap,
+    io::Result as IoResult,
+    process::{Child, Command, Stdio},
+    sync::Mutex,
+};
+
+use super::{system_find_by_name, system_has_process, ProcessBackend};
+
+/// Children are kept so that exited ones can be reaped instead of lingering as zombies,
+/// which would otherwise still be reported as running
+#[derive(Default)]
+pub struct UnixBackend {
+    children: Mutex<HashMap<u32, Child>>,
+}
+
+impl ProcessBackend for UnixBackend {
+    fn spawn(&self, executable: &str, args: &[String]) -> IoResult<u32> {
+        let child = Command::new(executable)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let pid = child.id();
+        self.children.lock().unwrap().insert(pid, child);
+
+        Ok(pid)
+    }
+
+    fn is_running(&self, pid: u32) -> bool {
+        let mut children = self.children.lock().unwrap();
+
+        if let Some(child) = children.get_mut(&pid) {
+            return match child.try_wait() {
+                Ok(None) => true,
+                _ => {
+                    children.remove(&pid);
+                    false
+                }
+            };
+        }
+
+        system_has_process(pid)
+    }
+
+    fn terminate(&self, pid: u32) -> bool {
+        let mut children = self.children.lock().unwrap();
+
+        if let Some(mut child) = children.remove(&pid) {
+            let killed = child.kill().is_ok();
+            let _ = child.wait();
+            return killed;
+        }
+
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+
+        unsafe { libc::kill(pid, libc::SIGKILL) == 0 }
+    }
+
+    fn find_by_name(&self, name: &str) -> Vec<u32> {
+        system_find_by_name(name)
+    }
+}
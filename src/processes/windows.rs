@@ -0,0 +1,50 @@
+use std::{
+    io::Result as IoResult,
+    os::windows::process::CommandExt,
+    process::{Command, Stdio},
+};
+use winapi::um::{
+    handleapi::CloseHandle, processthreadsapi::OpenProcess, processthreadsapi::TerminateProcess,
+    winbase::CREATE_NO_WINDOW, winnt::PROCESS_TERMINATE,
+};
+
+use super::{system_find_by_name, system_has_process, ProcessBackend};
+
+#[derive(Default)]
+pub struct WindowsBackend;
+
+impl ProcessBackend for WindowsBackend {
+    fn spawn(&self, executable: &str, args: &[String]) -> IoResult<u32> {
+        let child = Command::new(executable)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .creation_flags(CREATE_NO_WINDOW)
+            .spawn()?;
+
+        Ok(child.id())
+    }
+
+    fn is_running(&self, pid: u32) -> bool {
+        system_has_process(pid)
+    }
+
+    fn terminate(&self, pid: u32) -> bool {
+        unsafe {
+            let h_process = OpenProcess(PROCESS_TERMINATE, 0, pid);
+
+            if h_process.is_null() {
+                return false;
+            }
+
+            let success = TerminateProcess(h_process, 1);
+            CloseHandle(h_process);
+
+            success != 0
+        }
+    }
+
+    fn find_by_name(&self, name: &str) -> Vec<u32> {
+        system_find_by_name(name)
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    time::Instant,
+};
+use tiny_http::{Header, Method, Request, ResponseBox, StatusCode};
+
+use crate::errors::{HandlerResult, ServerError};
+use crate::logging::log_request;
+use crate::utilities::empty_response_with_status;
+
+/// A route handler returns the response, which the router sends and logs
+pub type Handler = Box<dyn Fn(&mut Request) -> HandlerResult + Send + Sync>;
+
+/// Which group of workers runs a route, so slow endpoints cannot starve quick ones
+#[derive(Clone, Copy, PartialEq)]
+pub enum Lane {
+    Fast,
+    Slow,
+}
+
+struct Route {
+    method: Method,
+    path: &'static str,
+    lane: Lane,
+    /// Whether HEAD requests are also served by this GET route
+    head: bool,
+    handler: Handler,
+}
+
+/// Maps a method and exact path to a handler
+///
+/// Unknown paths are answered with 404 and known paths requested with the wrong method with 405
+/// and an `Allow` header. HEAD is served by the GET handler of file and image routes (tiny_http
+/// omits the body), never by routes with side effects, and OPTIONS is answered with the allowed
+/// methods.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router { routes: Vec::new() }
+    }
+
+    pub fn route(
+        &mut self,
+        method: Method,
+        path: &'static str,
+        lane: Lane,
+        handler: impl Fn(&mut Request) -> HandlerResult + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.routes.push(Route {
+            method,
+            path,
+            lane,
+            head: false,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get(
+        &mut self,
+        path: &'static str,
+        handler: impl Fn(&mut Request) -> HandlerResult + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.route(Method::Get, path, Lane::Fast, handler)
+    }
+
+    /// Register a GET route that may take a long time, e.g. decoding or walking directories
+    pub fn get_slow(
+        &mut self,
+        path: &'static str,
+        handler: impl Fn(&mut Request) -> HandlerResult + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.route(Method::Get, path, Lane::Slow, handler)
+    }
+
+    /// Register a slow GET route returning a file or image, which HEAD requests may also use to
+    /// read its headers
+    pub fn get_file(
+        &mut self,
+        path: &'static str,
+        handler: impl Fn(&mut Request) -> HandlerResult + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.route(Method::Get, path, Lane::Slow, handler);
+        if let Some(route) = self.routes.last_mut() {
+            route.head = true;
+        }
+        self
+    }
+
+    pub fn post(
+        &mut self,
+        path: &'static str,
+        handler: impl Fn(&mut Request) -> HandlerResult + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.route(Method::Post, path, Lane::Fast, handler)
+    }
+
+    /// Register a POST route that may take a long time, e.g. encoding textures
+    pub fn post_slow(
+        &mut self,
+        path: &'static str,
+        handler: impl Fn(&mut Request) -> HandlerResult + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.route(Method::Post, path, Lane::Slow, handler)
+    }
+
+    /// Run the handler of a route returned by `resolve` and respond with its result
+    ///
+    /// A handler that panics, e.g. in a third-party decoder, is answered with 500 so the worker
+    /// running it survives.
+    pub fn handle(&self, route: usize, mut request: Request, started: Instant) {
+        let handler = &self.routes[route].handler;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| handler(&mut request)))
+            .unwrap_or_else(|payload| {
+                let reason = payload
+                    .downcast_ref::<&str>()
+                    .map(|reason| reason.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                Err(ServerError::internal(format!(
+                    "Request handler panicked: {}",
+                    reason
+                )))
+            });
+        respond(request, result, started);
+    }
+
+    pub fn lane(&self, route: usize) -> Lane {
+        self.routes[route].lane
+    }
+
+    /// Find the route for a request, or answer it directly if there is none
+    pub fn resolve(&self, request: Request, started: Instant) -> Option<(Request, usize)> {
+        let path = request_path(request.url()).to_string();
+        let method = request.method().clone();
+
+        let allowed = self.allowed_methods(&path);
+
+        if allowed.is_empty() {
+            let error = ServerError::not_found(format!("No endpoint at {}", path));
+            respond(request, Err(error), started);
+            return None;
+        }
+
+        if method == Method::Options {
+            let response = with_allow_header(empty_response_with_status(StatusCode(204)), &allowed);
+            respond(request, Ok(response), started);
+            return None;
+        }
+
+        let route = self.find(&method, &path).or_else(|| match method {
+            Method::Head => self
+                .find(&Method::Get, &path)
+                .filter(|route| self.routes[*route].head),
+            _ => None,
+        });
+
+        match route {
+            Some(route) => Some((request, route)),
+            None => {
+                let error = ServerError::method_not_allowed(format!(
+                    "{} does not accept {} requests",
+                    path, method
+                ));
+                log_request(&method, request.url(), error.status, started, Some(&error));
+                let _ = request.respond(with_allow_header(error.into_response(), &allowed));
+                None
+            }
+        }
+    }
+
+    fn find(&self, method: &Method, path: &str) -> Option<usize> {
+        self.routes
+            .iter()
+            .position(|route| route.method == *method && route.path == path)
+    }
+
+    /// Methods accepted for a path, including the implicit HEAD and OPTIONS
+    fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = Vec::new();
+        let mut head = false;
+
+        for route in self.routes.iter().filter(|route| route.path == path) {
+            if !methods.contains(&route.method) {
+                methods.push(route.method.clone());
+            }
+            head |= route.head;
+        }
+
+        if methods.is_empty() {
+            return methods;
+        }
+
+        for implicit in [Method::Head, Method::Options] {
+            let implied = implicit != Method::Head || head;
+
+            if implied && !methods.contains(&implicit) {
+                methods.push(implicit);
+            }
+        }
+
+        methods
+    }
+}
+
+/// Send a handler's result, rendering errors as JSON, and log the request
+pub fn respond(request: Request, result: HandlerResult, started: Instant) {
+    let response = match result {
+        Ok(response) => {
+            let status = response.status_code().0;
+            log_request(request.method(), request.url(), status, started, None);
+            response
+        }
+        Err(error) => {
+            log_request(
+                request.method(),
+                request.url(),
+                error.status,
+                started,
+                Some(&error),
+            );
+            error.into_response()
+        }
+    };
+
+    let _ = request.respond(response);
+}
+
+/// Return the path of a request URL without its query string
+pub fn request_path(url: &str) -> &str {
+    url.split('?').next().unwrap_or(url)
+}
+
+fn with_allow_header(response: ResponseBox, methods: &[Method]) -> ResponseBox {
+    let allow = methods
+        .iter()
+        .map(|method| method.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ");
+
+    match Header::from_bytes(&b"Allow"[..], allow.as_bytes()) {
+        Ok(header) => response.with_header(header),
+        Err(_) => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_http::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn panicking_handler_does_not_unwind_into_worker() {
+        let mut router = Router::new();
+        router.get("/panic", |_request| panic!("decoder failed"));
+
+        let request: Request = TestRequest::new().with_path("/panic").into();
+        let (request, route) = router.resolve(request, Instant::now()).unwrap();
+
+        router.handle(route, request, Instant::now());
+    }
+
+    #[test]
+    fn head_is_only_served_by_file_routes() {
+        let mut router = Router::new();
+        router
+            .get("/shutdown", |_request| {
+                Ok(empty_response_with_status(StatusCode(200)))
+            })
+            .get_file("/image", |_request| {
+                Ok(empty_response_with_status(StatusCode(200)))
+            });
+
+        let head = |path: &str| {
+            let request: Request = TestRequest::new()
+                .with_method(Method::Head)
+                .with_path(path)
+                .into();
+            router.resolve(request, Instant::now()).is_some()
+        };
+
+        assert!(head("/image"));
+        assert!(!head("/shutdown"));
+        assert!(!router.allowed_methods("/shutdown").contains(&Method::Head));
+    }
+}
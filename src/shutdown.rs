@@ -0,0 +1,74 @@
+use crossbeam::channel::{bounded, Receiver, Sender};
+use lazy_static::lazy_static;
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::constants::{EXIT_GAME_EXITED, EXIT_REQUESTED, EXIT_SIGNAL};
+
+/// Why the server is stopping, which decides its exit code
+#[derive(Clone, Copy, Debug)]
+pub enum ExitReason {
+    /// A client called /shutdown
+    Requested,
+    /// None of the watched game processes are running
+    GameExited,
+    /// Ctrl+C, closing the console or a termination signal
+    Signal,
+}
+
+impl ExitReason {
+    pub fn code(self) -> i32 {
+        match self {
+            ExitReason::Requested => EXIT_REQUESTED,
+            ExitReason::GameExited => EXIT_GAME_EXITED,
+            ExitReason::Signal => EXIT_SIGNAL,
+        }
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            ExitReason::Requested => "shutdown requested",
+            ExitReason::GameExited => "game exited",
+            ExitReason::Signal => "received termination signal",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref SHUTDOWN: (Sender<ExitReason>, Receiver<ExitReason>) = bounded(1);
+}
+
+/// Ask the main thread to shut down, returning false if a shutdown is already under way
+pub fn request_shutdown(reason: ExitReason) -> bool {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+
+    let _ = SHUTDOWN.0.send(reason);
+    true
+}
+
+/// Block until a shutdown is requested and return why
+pub fn wait_for_shutdown() -> ExitReason {
+    SHUTDOWN.1.recv().unwrap_or(ExitReason::Requested)
+}
+
+/// Shut down on Ctrl+C and termination signals, exiting at once if one arrives during shutdown
+pub fn handle_signals() {
+    let result = ctrlc::set_handler(|| {
+        if !request_shutdown(ExitReason::Signal) {
+            std::process::exit(ExitReason::Signal.code());
+        }
+    });
+
+    if let Err(err) = result {
+        log::warn!("Could not listen for termination signals: {}", err);
+    }
+}
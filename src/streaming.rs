@@ -0,0 +1,193 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
+use tiny_http::{Header, Request, Response, ResponseBox, StatusCode};
+
+use crate::cache::CacheKey;
+use crate::conditional::{range_allowed, with_key_validators};
+use crate::errors::ServerError;
+
+/// Inclusive byte offsets of a part of a file
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+/// Stream a file without buffering it, sending only the part asked for by a single `Range`
+///
+/// Multiple ranges are answered with the whole file, which RFC 9110 permits.
+pub fn file_response(
+    request: &Request,
+    mut file: File,
+    length: u64,
+    content_type: &str,
+    key: &Option<CacheKey>,
+) -> ResponseBox {
+    let range = match range_header(request) {
+        Some(value) if range_allowed(request, key) => parse_range(value, length),
+        _ => Ok(None),
+    };
+
+    let mut headers = vec![
+        Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap(),
+        Header::from_bytes(&b"Accept-Ranges"[..], &b"bytes"[..]).unwrap(),
+    ];
+
+    let response = match range {
+        Ok(Some(range)) if file.seek(SeekFrom::Start(range.start)).is_ok() => {
+            let part_length = range.end - range.start + 1;
+            headers.push(content_range(&format!(
+                "bytes {}-{}/{}",
+                range.start, range.end, length
+            )));
+
+            Response::new(
+                StatusCode(206),
+                headers,
+                file.take(part_length),
+                Some(part_length as usize),
+                None,
+            )
+            .with_chunked_threshold(usize::MAX)
+            .boxed()
+        }
+        // A large enough threshold keeps tiny_http from replacing Content-Length with chunks
+        Ok(_) => Response::new(StatusCode(200), headers, file, Some(length as usize), None)
+            .with_chunked_threshold(usize::MAX)
+            .boxed(),
+        Err(()) => {
+            let error = ServerError::new(
+                416,
+                "range_not_satisfiable",
+                format!("Range is outside the file's {} bytes", length),
+            );
+            return error
+                .into_response()
+                .with_header(content_range(&format!("bytes */{}", length)));
+        }
+    };
+
+    with_key_validators(response, key)
+}
+
+fn range_header(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Range"))
+        .map(|header| header.value.as_str())
+}
+
+fn content_range(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Range"[..], value.as_bytes()).unwrap()
+}
+
+/// Parse a `Range` header against a file's length
+///
+/// Returns `Ok(None)` when the header should be ignored (malformed or several ranges) and
+/// `Err` when the range lies outside the file.
+fn parse_range(value: &str, length: u64) -> Result<Option<ByteRange>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+
+    match (first.parse::<u64>(), last.parse::<u64>()) {
+        // bytes=start-end or bytes=start-
+        (Ok(start), parsed_last) => {
+            let end = match parsed_last {
+                Ok(end) if end < start => return Ok(None),
+                Ok(end) => end.min(length.saturating_sub(1)),
+                Err(_) if last.is_empty() => length.saturating_sub(1),
+                Err(_) => return Ok(None),
+            };
+
+            if start >= length {
+                Err(())
+            } else {
+                Ok(Some(ByteRange { start, end }))
+            }
+        }
+        // bytes=-suffix_length
+        (Err(_), Ok(suffix)) if first.is_empty() => {
+            if suffix == 0 || length == 0 {
+                Err(())
+            } else {
+                Ok(Some(ByteRange {
+                    start: length.saturating_sub(suffix),
+                    end: length - 1,
+                }))
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_http::TestRequest;
+
+    use super::*;
+
+    fn bounds(value: &str, length: u64) -> Result<Option<(u64, u64)>, ()> {
+        parse_range(value, length).map(|range| range.map(|range| (range.start, range.end)))
+    }
+
+    #[test]
+    fn reads_start_and_end() {
+        assert_eq!(bounds("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(bounds("bytes=500-", 1000), Ok(Some((500, 999))));
+        assert_eq!(bounds("bytes=-200", 1000), Ok(Some((800, 999))));
+    }
+
+    #[test]
+    fn clamps_to_the_end_of_the_file() {
+        assert_eq!(bounds("bytes=900-5000", 1000), Ok(Some((900, 999))));
+        assert_eq!(bounds("bytes=-5000", 1000), Ok(Some((0, 999))));
+    }
+
+    #[test]
+    fn ignores_an_end_before_the_start() {
+        assert_eq!(bounds("bytes=100-50", 1000), Ok(None));
+    }
+
+    #[test]
+    fn rejects_a_start_at_or_past_the_end() {
+        assert_eq!(bounds("bytes=1000-", 1000), Err(()));
+        assert_eq!(bounds("bytes=1500-2000", 1000), Err(()));
+        assert_eq!(bounds("bytes=-0", 1000), Err(()));
+        assert_eq!(bounds("bytes=0-", 0), Err(()));
+    }
+
+    #[test]
+    fn sends_the_whole_file_for_several_ranges() {
+        assert_eq!(bounds("bytes=0-99,200-299", 1000), Ok(None));
+        assert_eq!(bounds("bytes=-100, 0-1", 1000), Ok(None));
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        assert_eq!(bounds("items=0-99", 1000), Ok(None));
+        assert_eq!(bounds("bytes=abc", 1000), Ok(None));
+        assert_eq!(bounds("bytes=0-abc", 1000), Ok(None));
+    }
+
+    #[test]
+    fn answers_an_unsatisfiable_range_with_416() {
+        let request: Request = TestRequest::new()
+            .with_path("/image")
+            .with_header(Header::from_bytes(&b"Range"[..], &b"bytes=999999-"[..]).unwrap())
+            .into();
+        let file = File::open("Cargo.toml").unwrap();
+        let length = file.metadata().unwrap().len();
+
+        let response = file_response(&request, file, length, "text/plain", &None);
+        assert_eq!(response.status_code(), StatusCode(416));
+    }
+}
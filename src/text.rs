@@ -0,0 +1,368 @@
+use ab_glyph::{point, Font, FontArc, GlyphId, OutlinedGlyph, PxScale, ScaleFont};
+use image::{Rgb, Rgba, RgbaImage};
+use std::collections::HashMap;
+
+use crate::constants::{
+    DEFAULT_TEXT_SIZE, MAX_TEXTURE_SIZE, MAX_TEXT_LENGTH, MAX_TEXT_OUTLINE, MAX_TEXT_SIZE,
+    MIN_TEXT_SIZE,
+};
+use crate::errors::ServerError;
+use crate::transform::parse_colour;
+use crate::utilities::{bounded_param, required_param};
+
+/// How lines narrower than the widest one are placed
+#[derive(Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Centre,
+    Right,
+}
+
+/// Text and its styling, read from `text`, `size`, `colour`, `outline`, `outline_colour`,
+/// `max_width` and `align` query parameters
+pub struct TextRequest {
+    pub text: String,
+    /// Height of a line in pixels
+    pub size: u32,
+    pub colour: Rgb<u8>,
+    /// Width of the outline drawn around the text in pixels, 0 for none
+    pub outline: u32,
+    pub outline_colour: Rgb<u8>,
+    /// Width in pixels at which lines are wrapped, between words or CJK characters
+    pub max_width: Option<u32>,
+    pub align: Align,
+}
+
+/// Text rasterised onto a transparent image
+pub struct RenderedText {
+    pub image: RgbaImage,
+    pub lines: u32,
+    /// Distance from the top of the image to the first line's baseline
+    pub baseline: u32,
+    /// Left, top, width and height of the drawn pixels
+    pub bounds: [u32; 4],
+    /// Characters the font has no glyph for, drawn as its placeholder
+    pub missing_glyphs: u32,
+}
+
+impl TextRequest {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<TextRequest, ServerError> {
+        let text = required_param(params, "text")?;
+        if text.chars().count() > MAX_TEXT_LENGTH {
+            return Err(ServerError::out_of_range(format!(
+                "text is longer than {} characters",
+                MAX_TEXT_LENGTH
+            )));
+        }
+
+        let max_width = match params.get("max_width") {
+            None => None,
+            Some(_) => Some(bounded_param(params, "max_width", 0, 1, MAX_TEXTURE_SIZE)?),
+        };
+
+        let align = match params.get("align").map(|value| value.to_lowercase()) {
+            None => Align::Left,
+            Some(value) => match value.as_str() {
+                "left" => Align::Left,
+                "centre" | "center" => Align::Centre,
+                "right" => Align::Right,
+                _ => return Err(ServerError::invalid_parameter("align", &value)),
+            },
+        };
+
+        Ok(TextRequest {
+            text: text.clone(),
+            size: bounded_param(
+                params,
+                "size",
+                DEFAULT_TEXT_SIZE,
+                MIN_TEXT_SIZE,
+                MAX_TEXT_SIZE,
+            )?,
+            colour: colour_param(params, "colour", Rgb([255, 255, 255]))?,
+            outline: bounded_param(params, "outline", 0, 0, MAX_TEXT_OUTLINE)?,
+            outline_colour: colour_param(params, "outline_colour", Rgb([0, 0, 0]))?,
+            max_width,
+            align,
+        })
+    }
+
+    /// Lay out and draw the text, refusing images wider or taller than `limit`
+    pub fn render(&self, font: &FontArc, limit: u32) -> Result<RenderedText, ServerError> {
+        let font = font.as_scaled(PxScale::from(self.size as f32));
+        let lines = self.wrap(&font);
+
+        let widths: Vec<f32> = lines.iter().map(|line| line_width(&font, line)).collect();
+        let box_width = widths.iter().copied().fold(0.0, f32::max);
+        let line_height = font.height() + font.line_gap();
+        let box_height = (lines.len() - 1) as f32 * line_height + font.height();
+
+        let mut glyphs: Vec<OutlinedGlyph> = Vec::new();
+        let mut missing_glyphs = 0;
+
+        for (number, (line, width)) in lines.iter().zip(&widths).enumerate() {
+            let mut x = match self.align {
+                Align::Left => 0.0,
+                Align::Centre => (box_width - width) / 2.0,
+                Align::Right => box_width - width,
+            };
+            let y = font.ascent() + number as f32 * line_height;
+            let mut previous: Option<GlyphId> = None;
+
+            for character in line.chars() {
+                let id = font.glyph_id(character);
+                if id.0 == 0 && !character.is_whitespace() {
+                    missing_glyphs += 1;
+                }
+                if let Some(previous) = previous {
+                    x += font.kern(previous, id);
+                }
+
+                let glyph = id.with_scale_and_position(font.scale(), point(x, y));
+                if let Some(outlined) = font.outline_glyph(glyph) {
+                    glyphs.push(outlined);
+                }
+
+                x += font.h_advance(id);
+                previous = Some(id);
+            }
+        }
+
+        // Glyphs can reach outside their advance, e.g. italics or accents, so the image grows to fit
+        let margin = self.outline as f32;
+        let (mut left, mut top, mut right, mut bottom) = (0.0, 0.0, box_width, box_height);
+        for glyph in &glyphs {
+            let bounds = glyph.px_bounds();
+            left = bounds.min.x.min(left);
+            top = bounds.min.y.min(top);
+            right = bounds.max.x.max(right);
+            bottom = bounds.max.y.max(bottom);
+        }
+        let (left, top) = ((left - margin).floor(), (top - margin).floor());
+        let width = ((right + margin).ceil() - left).max(1.0) as u32;
+        let height = ((bottom + margin).ceil() - top).max(1.0) as u32;
+
+        if width > limit || height > limit {
+            return Err(ServerError::unprocessable(format!(
+                "The text would be {}x{}, larger than the max_texture_size of {}",
+                width, height, limit
+            )));
+        }
+
+        let mut fill = vec![0.0f32; (width * height) as usize];
+        for glyph in &glyphs {
+            let bounds = glyph.px_bounds();
+            let (origin_x, origin_y) = (bounds.min.x - left, bounds.min.y - top);
+
+            glyph.draw(|x, y, coverage| {
+                let (x, y) = (origin_x as u32 + x, origin_y as u32 + y);
+                if x < width && y < height {
+                    let pixel = &mut fill[(y * width + x) as usize];
+                    *pixel = pixel.max(coverage);
+                }
+            });
+        }
+
+        let stroke = outline_mask(&fill, width, height, self.outline);
+        let image = RgbaImage::from_fn(width, height, |x, y| {
+            let index = (y * width + x) as usize;
+            let fill = fill[index].clamp(0.0, 1.0);
+            let stroke = stroke.as_ref().map_or(0.0, |stroke| stroke[index]);
+            composite(self.colour, fill, self.outline_colour, stroke)
+        });
+
+        Ok(RenderedText {
+            bounds: drawn_bounds(&image),
+            image,
+            lines: lines.len() as u32,
+            baseline: (font.ascent() - top).round() as u32,
+            missing_glyphs,
+        })
+    }
+
+    /// Split the text into lines at line breaks and wherever a line would pass `max_width`
+    fn wrap<F: Font, S: ScaleFont<F>>(&self, font: &S) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for paragraph in self.text.split('\n') {
+            let paragraph = paragraph.trim_end_matches('\r');
+            let Some(max_width) = self.max_width.map(|width| width as f32) else {
+                lines.push(paragraph.to_string());
+                continue;
+            };
+
+            let mut line = String::new();
+            for segment in segments(paragraph) {
+                let candidate = format!("{}{}", line, segment);
+                if line_width(font, candidate.trim_end()) <= max_width {
+                    line = candidate;
+                    continue;
+                }
+
+                if !line.trim_end().is_empty() {
+                    lines.push(line.trim_end().to_string());
+                    line.clear();
+                }
+
+                // Words wider than a whole line are broken between characters
+                for character in segment.chars() {
+                    let candidate = format!("{}{}", line, character);
+                    if !line.is_empty() && line_width(font, candidate.trim_end()) > max_width {
+                        lines.push(line.trim_end().to_string());
+                        line.clear();
+                    }
+                    line.push(character);
+                }
+            }
+            lines.push(line.trim_end().to_string());
+        }
+
+        lines
+    }
+}
+
+fn colour_param(
+    params: &HashMap<String, String>,
+    name: &str,
+    default: Rgb<u8>,
+) -> Result<Rgb<u8>, ServerError> {
+    match params.get(name) {
+        None => Ok(default),
+        Some(value) => {
+            parse_colour(value).ok_or_else(|| ServerError::invalid_parameter(name, value))
+        }
+    }
+}
+
+/// Width of a line of text in pixels, including kerning
+fn line_width<F: Font, S: ScaleFont<F>>(font: &S, line: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous: Option<GlyphId> = None;
+
+    for character in line.chars() {
+        let id = font.glyph_id(character);
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+
+    width
+}
+
+/// Split text into the pieces a line may break after: words with their trailing spaces, and
+/// single CJK characters
+fn segments(text: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut characters = text.char_indices().peekable();
+
+    while let Some((index, character)) = characters.next() {
+        let end = index + character.len_utf8();
+        let breaks = match characters.peek() {
+            None => true,
+            Some((_, next)) => {
+                !next.is_whitespace()
+                    && (character.is_whitespace() || is_wide(character) || is_wide(*next))
+            }
+        };
+
+        if breaks {
+            segments.push(&text[start..end]);
+            start = end;
+        }
+    }
+
+    segments
+}
+
+/// Whether a character belongs to a script written without spaces, so lines may break around it
+fn is_wide(character: char) -> bool {
+    matches!(
+        character,
+        '\u{2E80}'..='\u{9FFF}' | '\u{AC00}'..='\u{D7AF}' | '\u{F900}'..='\u{FAFF}' | '\u{FF00}'..='\u{FFEF}'
+    )
+}
+
+/// Spread the text's coverage by `radius` pixels with a round, antialiased edge
+fn outline_mask(fill: &[f32], width: u32, height: u32, radius: u32) -> Option<Vec<f32>> {
+    if radius == 0 {
+        return None;
+    }
+
+    let radius = radius as i32;
+    let mut disc = Vec::new();
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            let weight = radius as f32 + 0.5 - ((dx * dx + dy * dy) as f32).sqrt();
+            if weight > 0.0 {
+                disc.push((dx, dy, weight.min(1.0)));
+            }
+        }
+    }
+
+    let (width, height) = (width as i32, height as i32);
+    let mut mask = vec![0.0f32; fill.len()];
+
+    for y in 0..height {
+        for x in 0..width {
+            let coverage = fill[(y * width + x) as usize];
+            if coverage <= 0.0 {
+                continue;
+            }
+
+            for (dx, dy, weight) in &disc {
+                let (x, y) = (x + dx, y + dy);
+                if x >= 0 && y >= 0 && x < width && y < height {
+                    let pixel = &mut mask[(y * width + x) as usize];
+                    *pixel = pixel.max(coverage * weight);
+                }
+            }
+        }
+    }
+
+    Some(mask)
+}
+
+/// Draw the text's colour over the outline's, both with straight alpha
+fn composite(colour: Rgb<u8>, fill: f32, outline_colour: Rgb<u8>, stroke: f32) -> Rgba<u8> {
+    let stroke = stroke * (1.0 - fill);
+    let alpha = fill + stroke;
+    if alpha <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+
+    let channel = |index: usize| {
+        let value = (colour[index] as f32 * fill + outline_colour[index] as f32 * stroke) / alpha;
+        value.round() as u8
+    };
+
+    Rgba([
+        channel(0),
+        channel(1),
+        channel(2),
+        (alpha * 255.0).round() as u8,
+    ])
+}
+
+/// Left, top, width and height of the pixels that are not fully transparent
+fn drawn_bounds(image: &RgbaImage) -> [u32; 4] {
+    let mut bounds: Option<[u32; 4]> = None;
+
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel[3] == 0 {
+            continue;
+        }
+        let [left, top, right, bottom] = bounds.get_or_insert([x, y, x, y]);
+        *left = (*left).min(x);
+        *top = (*top).min(y);
+        *right = (*right).max(x);
+        *bottom = (*bottom).max(y);
+    }
+
+    match bounds {
+        Some([left, top, right, bottom]) => [left, top, right - left + 1, bottom - top + 1],
+        None => [0, 0, 0, 0],
+    }
+}
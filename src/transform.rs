@@ -0,0 +1,433 @@
+use image::{
+    codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder},
+    imageops::FilterType,
+    ColorType, DynamicImage, ImageError, ImageFormat, ImageOutputFormat, Rgb, RgbImage,
+};
+use std::{collections::HashMap, io::Cursor, path::Path};
+
+use crate::constants::{DEFAULT_JPEG_QUALITY, DEFAULT_MAX_TEXTURE_SIZE, MAX_RESIZE_DIMENSION};
+use crate::errors::ServerError;
+use crate::CONFIG;
+
+/// Formats Darktide can load from a URL
+const GAME_FORMATS: &[ImageFormat] = &[ImageFormat::Png, ImageFormat::Jpeg];
+
+/// How a resized image fills the requested width and height
+#[derive(Clone, Copy, PartialEq)]
+pub enum Fit {
+    /// Scale to fit inside the box, keeping the aspect ratio
+    Contain,
+    /// Scale to cover the box, keeping the aspect ratio and cropping the overflow from the centre
+    Cover,
+    /// Stretch to exactly the box
+    Fill,
+}
+
+/// Target size for an image, read from `width`, `height`, `fit` and `filter` query parameters
+pub struct Resize {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    pub filter: FilterType,
+}
+
+impl Resize {
+    /// Return the requested resize, or `None` when neither `width` nor `height` is given
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Option<Resize>, ServerError> {
+        let width = dimension_param(params, "width")?;
+        let height = dimension_param(params, "height")?;
+
+        let fit = match params.get("fit").map(|value| value.to_lowercase()) {
+            None => Fit::Contain,
+            Some(value) => match value.as_str() {
+                "contain" => Fit::Contain,
+                "cover" => Fit::Cover,
+                "fill" => Fit::Fill,
+                _ => return Err(ServerError::invalid_parameter("fit", &value)),
+            },
+        };
+
+        let filter = match params.get("filter").map(|value| value.to_lowercase()) {
+            None => FilterType::Lanczos3,
+            Some(value) => match value.as_str() {
+                "nearest" => FilterType::Nearest,
+                "triangle" | "linear" => FilterType::Triangle,
+                "catmullrom" | "cubic" => FilterType::CatmullRom,
+                "gaussian" => FilterType::Gaussian,
+                "lanczos3" | "lanczos" => FilterType::Lanczos3,
+                _ => return Err(ServerError::invalid_parameter("filter", &value)),
+            },
+        };
+
+        if width.is_none() && height.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Resize {
+            width,
+            height,
+            fit,
+            filter,
+        }))
+    }
+
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let (source_width, source_height) = (image.width().max(1), image.height().max(1));
+
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => match self.fit {
+                Fit::Contain => image.resize(width, height, self.filter),
+                Fit::Cover => cover(image, width, height, self.filter),
+                Fit::Fill => image.resize_exact(width, height, self.filter),
+            },
+            // With only one side given the other follows the aspect ratio, whatever the fit. A
+            // very narrow or tall image would make that side huge, so it is kept to the same
+            // limit as the given one and the whole image shrunk to fit.
+            (Some(width), None) => {
+                let height = scaled(source_height, width, source_width);
+                if height > u64::from(MAX_RESIZE_DIMENSION) {
+                    return image.resize(width, MAX_RESIZE_DIMENSION, self.filter);
+                }
+                image.resize_exact(width, height as u32, self.filter)
+            }
+            (None, Some(height)) => {
+                let width = scaled(source_width, height, source_height);
+                if width > u64::from(MAX_RESIZE_DIMENSION) {
+                    return image.resize(MAX_RESIZE_DIMENSION, height, self.filter);
+                }
+                image.resize_exact(width as u32, height, self.filter)
+            }
+            (None, None) => image.clone(),
+        }
+    }
+}
+
+/// Format requested with the `format` query parameter
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    /// Keep PNG and JPEG as they are and convert anything else to one the game can load
+    Auto,
+}
+
+/// How a decoded image is written back out, read from `format`, `quality`, `compression` and
+/// `background` query parameters
+pub struct Encoding {
+    pub format: Option<OutputFormat>,
+    /// JPEG quality from 1 to 100
+    pub quality: Option<u8>,
+    /// PNG compression level
+    pub compression: Option<CompressionType>,
+    /// Colour transparent pixels are flattened onto, otherwise JPEG drops the alpha channel
+    pub background: Option<Rgb<u8>>,
+}
+
+impl Encoding {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Encoding, ServerError> {
+        let format = match params.get("format").map(|value| value.to_lowercase()) {
+            None => None,
+            Some(value) => Some(match value.as_str() {
+                "png" => OutputFormat::Png,
+                "jpg" | "jpeg" => OutputFormat::Jpeg,
+                "auto" => OutputFormat::Auto,
+                _ => return Err(ServerError::invalid_parameter("format", &value)),
+            }),
+        };
+
+        let quality = match params.get("quality") {
+            None => None,
+            Some(value) => match value.parse::<u8>() {
+                Ok(quality) if (1..=100).contains(&quality) => Some(quality),
+                _ => return Err(ServerError::invalid_parameter("quality", value)),
+            },
+        };
+
+        let compression = match params.get("compression").map(|value| value.to_lowercase()) {
+            None => None,
+            Some(value) => Some(match value.as_str() {
+                "fast" => CompressionType::Fast,
+                "default" => CompressionType::Default,
+                "best" => CompressionType::Best,
+                _ => return Err(ServerError::invalid_parameter("compression", &value)),
+            }),
+        };
+
+        let background = match params.get("background") {
+            None => None,
+            Some(value) => Some(
+                parse_colour(value)
+                    .ok_or_else(|| ServerError::invalid_parameter("background", value))?,
+            ),
+        };
+
+        Ok(Encoding {
+            format,
+            quality,
+            compression,
+            background,
+        })
+    }
+
+    /// Use `format` when the request does not name one
+    pub fn with_default_format(mut self, format: OutputFormat) -> Encoding {
+        self.format = self.format.or(Some(format));
+        self
+    }
+
+    /// Whether any option asks for the file to be written differently from how it is stored
+    pub fn is_requested(&self) -> bool {
+        self.format.is_some()
+            || self.quality.is_some()
+            || self.compression.is_some()
+            || self.background.is_some()
+    }
+
+    /// Whether a file in the source format has to be decoded and encoded again
+    pub fn transcodes(&self, source: Option<ImageFormat>) -> bool {
+        let changes_format = match self.format {
+            None => false,
+            Some(OutputFormat::Auto) => {
+                !source.is_some_and(|format| GAME_FORMATS.contains(&format))
+            }
+            Some(OutputFormat::Png) => source != Some(ImageFormat::Png),
+            Some(OutputFormat::Jpeg) => source != Some(ImageFormat::Jpeg),
+        };
+
+        let changes_encoding = match self.output_format(source) {
+            ImageOutputFormat::Jpeg(_) => self.quality.is_some(),
+            _ => self.compression.is_some(),
+        };
+
+        changes_format || changes_encoding || self.background.is_some()
+    }
+
+    /// Output format for an image decoded from the source format
+    pub fn output_format(&self, source: Option<ImageFormat>) -> ImageOutputFormat {
+        let quality = self.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+
+        match self.format {
+            Some(OutputFormat::Png) => ImageOutputFormat::Png,
+            Some(OutputFormat::Jpeg) => ImageOutputFormat::Jpeg(quality),
+            // Photos stay lossy, everything else is written losslessly
+            Some(OutputFormat::Auto) | None => match source {
+                Some(ImageFormat::Jpeg) => ImageOutputFormat::Jpeg(quality),
+                _ => ImageOutputFormat::Png,
+            },
+        }
+    }
+}
+
+/// Largest width or height of an image sent to the game, from config.json
+pub fn max_texture_size() -> u32 {
+    CONFIG
+        .read()
+        .unwrap()
+        .config
+        .max_texture_size
+        .unwrap_or(DEFAULT_MAX_TEXTURE_SIZE)
+}
+
+/// Scale an image down to fit within `limit` by `limit`, keeping the aspect ratio
+pub fn limit_size(image: DynamicImage, limit: u32) -> DynamicImage {
+    if image.width() <= limit && image.height() <= limit {
+        return image;
+    }
+
+    image.resize(limit, limit, FilterType::Lanczos3)
+}
+
+/// Width and height of an image file read from its header, or `None` if it isn't an image
+pub fn image_dimensions(path: &str) -> Option<(u32, u32)> {
+    image::io::Reader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// Detect a file's format from its contents, falling back to the extension for formats like TGA
+pub fn source_format(bytes: &[u8], path: &str) -> Option<ImageFormat> {
+    image::guess_format(bytes)
+        .ok()
+        .or_else(|| ImageFormat::from_path(Path::new(path)).ok())
+}
+
+/// Decode an image from memory, mapping failures to 415 or 422
+pub fn decode(
+    bytes: &[u8],
+    format: Option<ImageFormat>,
+    path: &str,
+) -> Result<DynamicImage, ServerError> {
+    let Some(format) = format else {
+        return Err(ServerError::unsupported_media_type(format!(
+            "Unrecognised image format: {}",
+            path
+        )));
+    };
+
+    image::load_from_memory_with_format(bytes, format).map_err(|err| match err {
+        ImageError::Unsupported(_) => {
+            ServerError::unsupported_media_type(format!("Unsupported image format: {}", path))
+        }
+        ImageError::Decoding(_) => {
+            ServerError::unprocessable(format!("Invalid image file {}: {}", path, err))
+        }
+        _ => ServerError::internal(format!("Could not decode {}: {}", path, err)),
+    })
+}
+
+/// Encode an image decoded from the source format, returning the bytes and their MIME type
+pub fn encode(
+    image: &DynamicImage,
+    encoding: &Encoding,
+    source: Option<ImageFormat>,
+) -> Result<(Vec<u8>, &'static str), ServerError> {
+    let format = encoding.output_format(source);
+    let mime_type = match format {
+        ImageOutputFormat::Jpeg(_) => "image/jpeg",
+        _ => "image/png",
+    };
+
+    let flattened;
+    let image = match encoding.background {
+        Some(background) if image.color().has_alpha() => {
+            flattened = DynamicImage::ImageRgb8(flatten(image, background));
+            &flattened
+        }
+        _ => image,
+    };
+
+    // JPEG has no alpha channel, and neither format stores HDR's floating point values
+    let converted;
+    let image = match (&format, image.color()) {
+        (ImageOutputFormat::Jpeg(_), ColorType::L8 | ColorType::Rgb8) => image,
+        (ImageOutputFormat::Jpeg(_), _) | (_, ColorType::Rgb32F) => {
+            converted = DynamicImage::ImageRgb8(image.to_rgb8());
+            &converted
+        }
+        (_, ColorType::Rgba32F) => {
+            converted = DynamicImage::ImageRgba8(image.to_rgba8());
+            &converted
+        }
+        _ => image,
+    };
+
+    let mut buffer = Cursor::new(Vec::new());
+    let result = match format {
+        ImageOutputFormat::Png => image.write_with_encoder(PngEncoder::new_with_quality(
+            &mut buffer,
+            encoding.compression.unwrap_or(CompressionType::Default),
+            PngFilterType::Adaptive,
+        )),
+        _ => image.write_to(&mut buffer, format),
+    };
+    result.map_err(|err| ServerError::internal(format!("Could not encode image: {}", err)))?;
+
+    Ok((buffer.into_inner(), mime_type))
+}
+
+/// Blend an image over a solid colour, removing its transparency
+fn flatten(image: &DynamicImage, background: Rgb<u8>) -> RgbImage {
+    let rgba = image.to_rgba8();
+
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let pixel = rgba.get_pixel(x, y);
+        let alpha = u16::from(pixel[3]);
+
+        Rgb(std::array::from_fn(|channel| {
+            let blended =
+                u16::from(pixel[channel]) * alpha + u16::from(background[channel]) * (255 - alpha);
+            ((blended + 127) / 255) as u8
+        }))
+    })
+}
+
+/// Parse a colour written as six hex digits, with or without a leading "#"
+pub fn parse_colour(value: &str) -> Option<Rgb<u8>> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+    Some(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+fn dimension_param(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<u32>, ServerError> {
+    match params.get(name) {
+        None => Ok(None),
+        Some(value) => match value.parse::<u32>() {
+            Ok(size) if (1..=MAX_RESIZE_DIMENSION).contains(&size) => Ok(Some(size)),
+            _ => Err(ServerError::invalid_parameter(name, value)),
+        },
+    }
+}
+
+/// Scale `size` by `target / source`, never returning zero
+fn scaled(size: u32, target: u32, source: u32) -> u64 {
+    ((u64::from(size) * u64::from(target) + u64::from(source) / 2) / u64::from(source)).max(1)
+}
+
+/// Fill `width` by `height` and crop the overflow from the centre
+///
+/// Unlike `DynamicImage::resize_to_fill` this crops before scaling, so a long thin image never
+/// becomes a huge intermediate one.
+fn cover(image: &DynamicImage, width: u32, height: u32, filter: FilterType) -> DynamicImage {
+    let (source_width, source_height) = (image.width().max(1), image.height().max(1));
+
+    let crop_width = scaled(source_height, width, height).min(u64::from(source_width)) as u32;
+    let crop_height = scaled(source_width, height, width).min(u64::from(source_height)) as u32;
+
+    image
+        .crop_imm(
+            (source_width - crop_width) / 2,
+            (source_height - crop_height) / 2,
+            crop_width,
+            crop_height,
+        )
+        .resize_exact(width, height, filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resize(width: Option<u32>, height: Option<u32>, fit: Fit) -> Resize {
+        Resize {
+            width,
+            height,
+            fit,
+            filter: FilterType::Nearest,
+        }
+    }
+
+    #[test]
+    fn one_side_stays_within_the_resize_limit() {
+        let image = DynamicImage::new_rgba8(1, 10_000);
+        let resized = resize(Some(MAX_RESIZE_DIMENSION), None, Fit::Contain).apply(&image);
+        assert_eq!(resized.height(), MAX_RESIZE_DIMENSION);
+
+        let image = DynamicImage::new_rgba8(10_000, 1);
+        let resized = resize(None, Some(MAX_RESIZE_DIMENSION), Fit::Contain).apply(&image);
+        assert_eq!(resized.width(), MAX_RESIZE_DIMENSION);
+    }
+
+    #[test]
+    fn one_side_follows_the_aspect_ratio() {
+        let image = DynamicImage::new_rgba8(400, 200);
+        let resized = resize(Some(100), None, Fit::Contain).apply(&image);
+        assert_eq!((resized.width(), resized.height()), (100, 50));
+    }
+
+    #[test]
+    fn cover_crops_before_scaling() {
+        let image = DynamicImage::new_rgba8(1, 10_000);
+        let resized = resize(Some(64), Some(32), Fit::Cover).apply(&image);
+        assert_eq!((resized.width(), resized.height()), (64, 32));
+    }
+}
@@ -1,6 +1,16 @@
 use serde::Serialize;
-use std::io::Cursor;
-use tiny_http::{Header, Response, StatusCode};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    io::{Cursor, Result as IoResult},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tiny_http::{Header, Request, Response, ResponseBox, StatusCode};
+use url::form_urlencoded;
+
+use crate::errors::ServerError;
 
 /// Splits a string into space-separated segments, ignoring spaces in quoted substrings
 ///
@@ -35,11 +45,111 @@ pub fn split_command(command: &str) -> Vec<String> {
     segments
 }
 
+/// Numbers temporary files so that workers writing the same file at once each get their own
+static TEMPORARY_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// Return a unique temporary path next to `path` to write before renaming it into place
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(
+        ".{}.tmp",
+        TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    PathBuf::from(name)
+}
+
+/// Return the directory containing the server executable, where config and logs are kept
+pub fn executable_dir() -> IoResult<PathBuf> {
+    let mut bin_path = env::current_exe()?;
+    bin_path.pop(); // Get directory only, not executable itself
+
+    Ok(bin_path)
+}
+
+/// Resolve a directory setting, or `default` if it is not set, relative to the executable unless
+/// absolute
+pub fn configured_directory(value: Option<&str>, default: &str) -> IoResult<PathBuf> {
+    let directory = PathBuf::from(value.unwrap_or(default));
+
+    if directory.is_absolute() {
+        return Ok(directory);
+    }
+
+    executable_dir().map(|dir| dir.join(directory))
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date (Howard Hinnant's algorithm)
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Convert a (year, month, day) date to days since 1970-01-01, the inverse of `civil_from_days`
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Return the decoded query parameters of a request
+pub fn query_params(request: &Request) -> HashMap<String, String> {
+    let query_part = request.url().split_once('?').map_or("", |(_, query)| query);
+
+    form_urlencoded::parse(query_part.as_bytes())
+        .into_owned()
+        .collect()
+}
+
+/// Return a query parameter that must be present
+pub fn required_param<'a>(
+    params: &'a HashMap<String, String>,
+    name: &str,
+) -> Result<&'a String, ServerError> {
+    params
+        .get(name)
+        .ok_or_else(|| ServerError::missing_parameter(name))
+}
+
+/// Return a numeric query parameter between `min` and `max`, or `default` if it is missing
+pub fn bounded_param(
+    params: &HashMap<String, String>,
+    name: &str,
+    default: u32,
+    min: u32,
+    max: u32,
+) -> Result<u32, ServerError> {
+    match params.get(name) {
+        None => Ok(default),
+        Some(value) => match value.parse::<u32>() {
+            Ok(number) if (min..=max).contains(&number) => Ok(number),
+            _ => Err(ServerError::invalid_parameter(name, value)),
+        },
+    }
+}
+
 /// Return a JSON response with the given data and status code
 pub fn json_response_with_status<T: Serialize>(
     status: StatusCode,
     data: &T,
-) -> Response<Cursor<Vec<u8>>> {
+) -> ResponseBox {
     let json_bytes = serde_json::to_vec(data).unwrap_or_else(|_| vec![]);
     let content_length = json_bytes.len();
     let cursor = Cursor::new(json_bytes);
@@ -47,7 +157,7 @@ pub fn json_response_with_status<T: Serialize>(
     let mut response = Response::new(status, vec![], cursor, Some(content_length), None);
     let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
     response.add_header(header);
-    response
+    response.boxed()
 }
 
 // /// Return a response with a boolean value as a string and status code 200 OK
@@ -60,7 +170,28 @@ pub fn json_response_with_status<T: Serialize>(
 //     Response::new(status, vec![], cursor, None, None)
 // }
 
+/// Return a 200 response with the given bytes and content type
+pub fn bytes_response(bytes: Vec<u8>, content_type: &str) -> ResponseBox {
+    let content_length = bytes.len();
+    let header = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap();
+
+    Response::new(
+        StatusCode(200),
+        vec![header],
+        Cursor::new(bytes),
+        Some(content_length),
+        None,
+    )
+    .with_chunked_threshold(usize::MAX)
+    .boxed()
+}
+
+/// Return a header describing a response, e.g. the dimensions of raw pixels
+pub fn custom_header(name: &str, value: impl ToString) -> Header {
+    Header::from_bytes(name.as_bytes(), value.to_string().as_bytes()).unwrap()
+}
+
 /// Return an empty response with the given status code
-pub fn empty_response_with_status(status: StatusCode) -> Response<Cursor<Vec<u8>>> {
-    Response::new(status, vec![], Cursor::new(vec![]), None, None)
+pub fn empty_response_with_status(status: StatusCode) -> ResponseBox {
+    Response::new(status, vec![], Cursor::new(vec![]), None, None).boxed()
 }
@@ -0,0 +1,172 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::constants::{
+    DARKTIDE_PROCESS_NAME, DEFAULT_WATCHDOG_GRACE_SECONDS, DEFAULT_WATCHDOG_INTERVAL_MS,
+};
+use crate::processes::{is_any_running, ProcessBackend};
+use crate::shutdown::{request_shutdown, ExitReason};
+use crate::CONFIG;
+
+/// When the watched processes were last seen and whether they have gone missing since
+struct Watch {
+    last_seen: Instant,
+    missing: bool,
+}
+
+/// What changed for the watched processes in one poll
+#[derive(Debug, PartialEq)]
+enum Change {
+    None,
+    Returned,
+    WentMissing,
+    GracePeriodOver,
+}
+
+impl Watch {
+    fn new(now: Instant) -> Self {
+        Watch {
+            last_seen: now,
+            missing: false,
+        }
+    }
+
+    /// Record one poll and decide whether the grace period has run out
+    fn poll(&mut self, running: bool, now: Instant, grace_period: Duration) -> Change {
+        if running {
+            let returned = self.missing;
+            self.last_seen = now;
+            self.missing = false;
+
+            if returned {
+                Change::Returned
+            } else {
+                Change::None
+            }
+        } else if now.duration_since(self.last_seen) >= grace_period {
+            Change::GracePeriodOver
+        } else if !self.missing {
+            self.missing = true;
+            Change::WentMissing
+        } else {
+            Change::None
+        }
+    }
+}
+
+/// Shut the server down once none of the watched processes have been seen for the grace period
+pub fn watch_game(backend: &'static dyn ProcessBackend) {
+    thread::spawn(move || {
+        let mut watch = Watch::new(Instant::now());
+
+        loop {
+            let settings = CONFIG
+                .read()
+                .unwrap()
+                .config
+                .watchdog
+                .clone()
+                .unwrap_or_default()
+                .resolved();
+            let names = settings
+                .process_names
+                .unwrap_or(vec![DARKTIDE_PROCESS_NAME.to_string()]);
+            let grace_period = Duration::from_secs(
+                settings
+                    .grace_period_seconds
+                    .unwrap_or(DEFAULT_WATCHDOG_GRACE_SECONDS),
+            );
+
+            let running = settings.enabled == Some(false) || is_any_running(backend, &names);
+
+            match watch.poll(running, Instant::now(), grace_period) {
+                Change::None => {}
+                Change::Returned => log::info!("{} is running again", names.join(", ")),
+                Change::WentMissing => log::info!(
+                    "{} is not running, shutting down in {} seconds unless it restarts",
+                    names.join(", "),
+                    grace_period.as_secs()
+                ),
+                Change::GracePeriodOver => {
+                    log::info!("{} is not running. Shutting down.", names.join(", "));
+                    request_shutdown(ExitReason::GameExited);
+                    return;
+                }
+            }
+
+            thread::sleep(Duration::from_millis(
+                settings
+                    .poll_interval_ms
+                    .unwrap_or(DEFAULT_WATCHDOG_INTERVAL_MS),
+            ));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processes::mock::MockBackend;
+
+    const GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn shuts_down_only_after_the_grace_period() {
+        let backend = MockBackend::new().with_process(42, "Darktide.exe");
+        let names = vec![DARKTIDE_PROCESS_NAME.to_string()];
+        let start = Instant::now();
+        let mut watch = Watch::new(start);
+
+        let running = is_any_running(&backend, &names);
+        assert_eq!(watch.poll(running, start, GRACE_PERIOD), Change::None);
+
+        backend.terminate(42);
+        let running = is_any_running(&backend, &names);
+        let later = |seconds| start + Duration::from_secs(seconds);
+        assert_eq!(
+            watch.poll(running, later(1), GRACE_PERIOD),
+            Change::WentMissing
+        );
+        assert_eq!(watch.poll(running, later(29), GRACE_PERIOD), Change::None);
+        assert_eq!(
+            watch.poll(running, later(30), GRACE_PERIOD),
+            Change::GracePeriodOver
+        );
+    }
+
+    #[test]
+    fn restarting_within_the_grace_period_resets_it() {
+        let backend = MockBackend::new();
+        let names = vec![DARKTIDE_PROCESS_NAME.to_string()];
+        let start = Instant::now();
+        let later = |seconds| start + Duration::from_secs(seconds);
+        let mut watch = Watch::new(start);
+
+        let running = is_any_running(&backend, &names);
+        assert_eq!(
+            watch.poll(running, later(20), GRACE_PERIOD),
+            Change::WentMissing
+        );
+
+        backend.spawn("C:/Darktide/Darktide.exe", &[]).unwrap();
+        let running = is_any_running(&backend, &names);
+        assert_eq!(
+            watch.poll(running, later(25), GRACE_PERIOD),
+            Change::Returned
+        );
+        assert_eq!(watch.poll(running, later(26), GRACE_PERIOD), Change::None);
+
+        backend.terminate(1000);
+        let running = is_any_running(&backend, &names);
+        assert_eq!(
+            watch.poll(running, later(50), GRACE_PERIOD),
+            Change::WentMissing
+        );
+        assert_eq!(
+            watch.poll(running, later(56), GRACE_PERIOD),
+            Change::GracePeriodOver
+        );
+    }
+}
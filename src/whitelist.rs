@@ -0,0 +1,174 @@
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    ffi::OsString,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::constants::{builtin_executables, AllowedExecutable};
+use crate::CONFIG;
+
+/// Check a command's executable against the built-in and user whitelists
+///
+/// Returns the path to spawn if some entry matches on name and, where the entry restricts them,
+/// on absolute path and SHA-256 of the binary.
+pub fn authorize_executable(executable: &str) -> Option<PathBuf> {
+    let configured = CONFIG
+        .read()
+        .unwrap()
+        .config
+        .allowed_executables
+        .clone()
+        .unwrap_or_default();
+
+    entries_named(normalized_name(executable), configured)
+        .iter()
+        .find_map(|entry| matches_entry(executable, entry))
+}
+
+/// Whitelist entries for an executable name, matched exactly as the name is written
+///
+/// Entries in config.json replace the built-in entry of the same name, so that pinning a path or
+/// hash for a built-in executable restricts it rather than adding another way to run it.
+fn entries_named(name: &str, configured: Vec<AllowedExecutable>) -> Vec<AllowedExecutable> {
+    let mut entries: Vec<AllowedExecutable> = configured
+        .into_iter()
+        .filter(|entry| entry.name == name)
+        .collect();
+
+    if entries.is_empty() {
+        entries = builtin_executables()
+            .into_iter()
+            .filter(|entry| entry.name == name)
+            .collect();
+    }
+
+    entries
+}
+
+/// Return the executable's file name without any directories or ".exe" extension
+pub fn normalized_name(executable: &str) -> &str {
+    // Get segment after the last slash (if any)
+    let executable_name = Path::new(executable)
+        .file_name()
+        .and_then(|os_str| os_str.to_str())
+        .unwrap_or(executable);
+
+    // Remove ".exe" extension (if any)
+    if executable_name.to_lowercase().ends_with(".exe") {
+        &executable_name[0..executable_name.len() - 4]
+    } else {
+        executable_name
+    }
+}
+
+fn matches_entry(executable: &str, entry: &AllowedExecutable) -> Option<PathBuf> {
+    if entry.path.is_none() && entry.sha256.is_none() {
+        return Some(PathBuf::from(executable));
+    }
+
+    let resolved = resolve_executable(executable)?.canonicalize().ok()?;
+
+    if let Some(allowed_path) = &entry.path {
+        if Path::new(allowed_path).canonicalize().ok()? != resolved {
+            return None;
+        }
+    }
+
+    if let Some(allowed_hash) = &entry.sha256 {
+        if !sha256_file(&resolved)
+            .ok()?
+            .eq_ignore_ascii_case(allowed_hash)
+        {
+            return None;
+        }
+    }
+
+    Some(resolved)
+}
+
+/// Find the file a command would run, searching PATH for bare names
+fn resolve_executable(executable: &str) -> Option<PathBuf> {
+    let path = Path::new(executable);
+
+    if path.components().count() > 1 {
+        return with_exe_extension(path);
+    }
+
+    env::split_paths(&env::var_os("PATH")?).find_map(|dir| with_exe_extension(&dir.join(path)))
+}
+
+fn with_exe_extension(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+
+    if cfg!(windows) {
+        let mut with_extension = OsString::from(path);
+        with_extension.push(".exe");
+
+        let with_extension = PathBuf::from(with_extension);
+        if with_extension.is_file() {
+            return Some(with_extension);
+        }
+    }
+
+    None
+}
+
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Whether a string is a hex encoded SHA-256 digest
+pub fn is_sha256_hex(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|char| char.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, sha256: Option<&str>) -> AllowedExecutable {
+        AllowedExecutable {
+            name: name.to_string(),
+            path: None,
+            sha256: sha256.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn matches_names_exactly() {
+        assert_eq!(entries_named("ffplay_dt", Vec::new()).len(), 1);
+        assert!(entries_named("FFPLAY_DT", Vec::new()).is_empty());
+        assert!(entries_named("ffplay", Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn configured_entry_replaces_builtin_entry() {
+        let hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let entries = entries_named("ffplay_dt", vec![entry("ffplay_dt", Some(hash))]);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].sha256.as_deref(), Some(hash));
+        assert!(matches_entry("ffplay_dt", &entries[0]).is_none());
+    }
+
+    #[test]
+    fn configured_entries_add_new_names() {
+        let configured = vec![entry("my_tool", None), entry("other_tool", None)];
+
+        assert_eq!(entries_named("my_tool", configured.clone()).len(), 1);
+        assert_eq!(entries_named("ffplay_dt", configured).len(), 1);
+    }
+}